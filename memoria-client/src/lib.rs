@@ -0,0 +1,189 @@
+//! Typed client for the memoria daemon's line-delimited JSON protocol.
+//!
+//! ```no_run
+//! # async fn run() -> anyhow::Result<()> {
+//! let mut client = memoria_client::Client::connect().await?;
+//! for item in client.list(10).await? {
+//!     println!("{} {}", item.id, item.title.unwrap_or_default());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+mod protocol;
+
+pub use protocol::{default_socket_path, IpcError, ItemSummary};
+
+use anyhow::{anyhow, Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fmt;
+use std::path::Path;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::UnixStream;
+
+/// An error response from the daemon. Requests that fail this way return it
+/// inside the `anyhow::Error`, so callers can `downcast_ref` to branch on `code`.
+#[derive(Debug, Clone)]
+pub struct DaemonError {
+    pub code: Option<IpcError>,
+    pub message: String,
+}
+
+impl fmt::Display for DaemonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for DaemonError {}
+
+#[derive(Deserialize)]
+struct Response {
+    ok: bool,
+    data: Option<Value>,
+    error: Option<String>,
+    code: Option<IpcError>,
+}
+
+/// One connection to the daemon. Requests are sent one at a time.
+pub struct Client {
+    lines: Lines<BufReader<OwnedReadHalf>>,
+    writer: OwnedWriteHalf,
+}
+
+impl Client {
+    /// Connects to the socket at `default_socket_path`.
+    pub async fn connect() -> Result<Self> {
+        Self::connect_to(&default_socket_path()).await
+    }
+
+    pub async fn connect_to(path: &Path) -> Result<Self> {
+        let stream = UnixStream::connect(path)
+            .await
+            .with_context(|| format!("failed to connect to {}", path.display()))?;
+        let (reader, writer) = stream.into_split();
+        Ok(Self { lines: BufReader::new(reader).lines(), writer })
+    }
+
+    /// Sends `cmd` with `args` (an object, or null for none) and returns the
+    /// response's `data`.
+    pub async fn request(&mut self, cmd: &str, args: Value) -> Result<Value> {
+        let mut line = serde_json::to_string(&json!({ "cmd": cmd, "args": args }))?;
+        line.push('\n');
+        self.writer.write_all(line.as_bytes()).await.context("failed to send request")?;
+
+        let reply = self
+            .lines
+            .next_line()
+            .await
+            .context("failed to read response")?
+            .ok_or_else(|| anyhow!("daemon closed the connection"))?;
+        let response: Response = serde_json::from_str(&reply).context("malformed response")?;
+
+        if response.ok {
+            Ok(response.data.unwrap_or(Value::Null))
+        } else {
+            Err(DaemonError {
+                code: response.code,
+                message: response.error.unwrap_or_else(|| "unknown error".to_string()),
+            }
+            .into())
+        }
+    }
+
+    async fn request_as<T: DeserializeOwned>(&mut self, cmd: &str, args: Value) -> Result<T> {
+        let data = self.request(cmd, args).await?;
+        serde_json::from_value(data).with_context(|| format!("unexpected {cmd} response"))
+    }
+
+    pub async fn ping(&mut self) -> Result<Value> {
+        self.request("ping", Value::Null).await
+    }
+
+    /// Most recent items in the daemon's default order, with full bodies.
+    pub async fn list(&mut self, limit: u32) -> Result<Vec<ItemSummary>> {
+        self.request_as("list", json!({ "limit": limit, "full_body": true })).await
+    }
+
+    /// Number of items, or only starred ones, without fetching them.
+    pub async fn count(&mut self, starred_only: bool) -> Result<u64> {
+        let data = self.request("count", json!({ "starred_only": starred_only })).await?;
+        data["count"].as_u64().ok_or_else(|| anyhow!("unexpected count response"))
+    }
+
+    pub async fn search(&mut self, query: &str, limit: u32) -> Result<Vec<ItemSummary>> {
+        self.request_as("search", json!({ "query": query, "limit": limit, "full_body": true }))
+            .await
+    }
+
+    pub async fn get(&mut self, id: i64) -> Result<ItemSummary> {
+        self.request_as("get", json!({ "id": id })).await
+    }
+
+    /// Puts item `id` back on the selection it was captured from.
+    pub async fn copy(&mut self, id: i64) -> Result<()> {
+        self.request("copy", json!({ "id": id })).await.map(drop)
+    }
+
+    /// Marks item `id` as used without copying it; returns its new `last_used`.
+    pub async fn touch(&mut self, id: i64) -> Result<i64> {
+        let data = self.request("touch", json!({ "id": id })).await?;
+        data["last_used"].as_i64().ok_or_else(|| anyhow!("unexpected touch response"))
+    }
+
+    /// Like `copy`, restoring the representation stored as `mime`.
+    pub async fn copy_as(&mut self, id: i64, mime: &str) -> Result<()> {
+        self.request("copy", json!({ "id": id, "mime": mime })).await.map(drop)
+    }
+
+    /// Replaces the title of item `id`.
+    pub async fn rename(&mut self, id: i64, title: &str) -> Result<()> {
+        self.request("rename", json!({ "id": id, "title": title })).await.map(drop)
+    }
+
+    /// Corrects the kind of text item `id`; `language` only goes with `code`.
+    pub async fn set_kind(&mut self, id: i64, kind: &str, language: Option<&str>) -> Result<()> {
+        self.request("set_kind", json!({ "id": id, "kind": kind, "language": language }))
+            .await
+            .map(drop)
+    }
+
+    /// Returns how many items were updated.
+    pub async fn star(&mut self, ids: &[i64], value: bool) -> Result<u64> {
+        let data = self.request("star", json!({ "ids": ids, "value": value })).await?;
+        Ok(data["updated"].as_u64().unwrap_or(0))
+    }
+
+    /// Deletes (or trashes, if the daemon has a trash) unstarred items among
+    /// `ids`, returning how many were removed.
+    pub async fn delete(&mut self, ids: &[i64]) -> Result<u64> {
+        let data = self.request("delete", json!({ "ids": ids })).await?;
+        Ok(data["deleted"].as_u64().unwrap_or(0))
+    }
+
+    /// Takes items out of the trash, returning how many were restored.
+    pub async fn restore(&mut self, ids: &[i64]) -> Result<u64> {
+        let data = self.request("restore", json!({ "ids": ids })).await?;
+        Ok(data["restored"].as_u64().unwrap_or(0))
+    }
+    /// Stops or restarts clipboard capture; the daemon keeps running either way.
+    pub async fn set_paused(&mut self, paused: bool) -> Result<()> {
+        self.request(if paused { "pause" } else { "resume" }, Value::Null).await.map(drop)
+    }
+
+    /// Whether clipboard capture is currently paused.
+    pub async fn is_paused(&mut self) -> Result<bool> {
+        let data = self.request("status", Value::Null).await?;
+        data["paused"].as_bool().ok_or_else(|| anyhow!("unexpected status response"))
+    }
+
+    /// Suspends capture for `minutes`, replacing any incognito window already
+    /// running; returns when capture resumes, as a unix timestamp.
+    pub async fn incognito(&mut self, minutes: u32) -> Result<i64> {
+        let data = self.request("incognito", json!({ "minutes": minutes })).await?;
+        data["resume_at"].as_i64().ok_or_else(|| anyhow!("unexpected incognito response"))
+    }
+}
@@ -0,0 +1,91 @@
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+
+/// One clipboard item as sent by `list`, `search`, `gallery`, `get` and `trash`.
+///
+/// Listings replace `body` with a preview unless the request sets
+/// `full_body`, so `body` may be absent.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ItemSummary {
+    pub id: i64,
+    pub title: Option<String>,
+    pub body: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    pub last_used: Option<i64>,
+    pub starred: bool,
+    pub hash: Option<String>,
+    pub has_image: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub thumbnail_path: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+    /// App id of the window the item was copied from; see `behavior.capture_source`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_app: Option<String>,
+    pub kind: Option<String>,
+    /// Best-guess language of a `code` item, e.g. `rust`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    /// `clipboard` or `primary`.
+    pub selection: String,
+    pub tags: Vec<String>,
+    pub note: Option<String>,
+    /// Times the item was copied or touched, including dedupe hits.
+    pub use_count: i64,
+    /// When the item was moved to the trash; only set in `trash` listings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deleted_at: Option<i64>,
+    /// Pixel dimensions and size of the original image, for image items.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub byte_size: Option<i64>,
+    /// Paths of a `files` item, in the order they were copied.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub files: Vec<String>,
+    /// The `files` that no longer exist, checked when the item is listed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub missing_files: Vec<String>,
+    /// Representations `copy` can restore via `mime`; the text or image the
+    /// item was recorded from comes first.
+    #[serde(default)]
+    pub mimes: Vec<String>,
+    /// A `text/html` representation was captured, so `copy` with
+    /// `prefer_html` restores formatting.
+    #[serde(default)]
+    pub has_html: bool,
+    /// Found by the fuzzy pass of `search` rather than the full-text index.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub fuzzy: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+}
+
+/// Error categories clients can branch on; `error` keeps the human-readable text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IpcError {
+    /// Malformed JSON or a request the daemon rejected.
+    InvalidRequest,
+    NotFound,
+    LockPoisoned,
+    Unauthorized,
+    /// `paste` found none of the paste tools (`wtype`, `ydotool` or the
+    /// configured `paste_command`) installed.
+    PasteToolUnavailable,
+    Internal,
+}
+
+/// Where the daemon listens: `$XDG_RUNTIME_DIR/memoria.sock`, or
+/// `/run/user/$UID/memoria.sock` when that is unset.
+pub fn default_socket_path() -> PathBuf {
+    if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
+        return PathBuf::from(dir).join("memoria.sock");
+    }
+
+    let uid = unsafe { libc::geteuid() };
+    PathBuf::from(format!("/run/user/{uid}/memoria.sock"))
+}
@@ -0,0 +1,144 @@
+use anyhow::{Context, Result};
+use std::future::Future;
+use std::time::Duration;
+use tracing::{debug, error, info};
+
+use crate::clipboard::Selection;
+
+/// Where the watcher reads selections from. The watcher waits on `changed`
+/// and then reads the mimes it cares about, so a backend only has to say
+/// when to look and hand over bytes.
+pub trait ClipboardBackend: Send + Sync + 'static {
+    fn name(&self) -> &'static str;
+
+    /// Resolves when `selection` may have changed since the last call.
+    fn changed(&self, selection: Selection) -> impl Future<Output = ()> + Send;
+
+    /// Mimes `selection` is currently offered as; empty when it is empty.
+    fn mimes(&self, selection: Selection) -> impl Future<Output = Result<Vec<String>>> + Send;
+
+    /// Contents of `selection` as `mime`; empty when the selection is empty or
+    /// isn't offered as `mime`.
+    fn read(&self, selection: Selection, mime: &str) -> impl Future<Output = Result<Vec<u8>>> + Send;
+}
+
+/// Polls by running `wl-paste` for every read.
+pub struct WlPaste;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+impl ClipboardBackend for WlPaste {
+    fn name(&self) -> &'static str {
+        "wl-paste"
+    }
+
+    async fn changed(&self, _selection: Selection) {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+
+    async fn mimes(&self, selection: Selection) -> Result<Vec<String>> {
+        let output = tokio::process::Command::new("wl-paste")
+            .args(selection.wl_args())
+            .arg("--list-types")
+            .output()
+            .await
+            .context("failed to run wl-paste --list-types")?;
+
+        if !output.status.success() {
+            return Ok(Vec::new());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect())
+    }
+
+    async fn read(&self, selection: Selection, mime: &str) -> Result<Vec<u8>> {
+        let output = tokio::process::Command::new("wl-paste")
+            .args(selection.wl_args())
+            .arg("--type")
+            .arg(mime)
+            .output()
+            .await
+            .context(format!("failed to run wl-paste for {}", mime));
+
+        match output {
+            Ok(output) if output.status.success() => Ok(output.stdout),
+            Ok(_) => Ok(Vec::new()),
+            // Hold the watcher here until wl-paste is back rather than
+            // failing every poll.
+            Err(err) if is_not_found(&err) => {
+                wait_for_wl_paste().await;
+                Err(err)
+            }
+            Err(err) => Err(err),
+        }
+    }
+}
+
+/// First delay between checks for a missing `wl-paste`; doubles each time
+/// up to `MAX_MISSING_BACKOFF`.
+const MIN_MISSING_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_MISSING_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Returns once `wl-paste` is on `PATH`. While it is missing, logs a single
+/// error and re-checks with exponential backoff; IPC is served meanwhile.
+pub async fn wait_for_wl_paste() {
+    let mut backoff = MIN_MISSING_BACKOFF;
+    let mut reported = false;
+
+    while !wl_paste_available().await {
+        if reported {
+            debug!(retry_secs = backoff.as_secs(), "wl-paste still missing");
+        } else {
+            error!("wl-paste not found in PATH - install wl-clipboard; clipboard monitoring resumes once it is available");
+            reported = true;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_MISSING_BACKOFF);
+    }
+
+    if reported {
+        info!("wl-paste found, clipboard monitoring resumed");
+    }
+}
+
+async fn wl_paste_available() -> bool {
+    program_available("wl-paste").await
+}
+
+/// Whether `program` can be spawned, probed with `--version` rather than
+/// `which`, which minimal systems lack. Only a missing program counts; one
+/// that runs and fails is still installed.
+async fn program_available(program: &str) -> bool {
+    let probe = tokio::process::Command::new(program)
+        .arg("--version")
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .await
+        .with_context(|| format!("failed to run {program} --version"));
+    !matches!(probe, Err(err) if is_not_found(&err))
+}
+
+/// Whether `err` comes from spawning a program that doesn't exist.
+fn is_not_found(err: &anyhow::Error) -> bool {
+    err.chain()
+        .filter_map(|cause| cause.downcast_ref::<std::io::Error>())
+        .any(|io| io.kind() == std::io::ErrorKind::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn probes_programs_without_which() {
+        assert!(program_available("sh").await);
+        // Exits non-zero on `--version`, but is installed.
+        assert!(program_available("false").await);
+        assert!(!program_available("memoria-no-such-program").await);
+    }
+}
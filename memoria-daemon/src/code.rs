@@ -0,0 +1,171 @@
+/// Lines a snippet needs before structure alone (no telltale keyword) makes
+/// it code; shorter text is too easy to mistake.
+const MIN_STRUCTURAL_LINES: usize = 3;
+
+/// Whether `text` reads as source code: a recognised language, or enough
+/// lines ending in `;`, `{` or `}` or indented under such lines.
+pub fn looks_like_code(text: &str) -> bool {
+    language(text).is_some() || structural(text)
+}
+
+/// Best guess at the language of `text`; `None` when nothing gives it away.
+/// Checked in order, most distinctive first.
+pub fn language(text: &str) -> Option<&'static str> {
+    let text = text.trim();
+    if let Some(shebang) = text.strip_prefix("#!") {
+        let interpreter = shebang.lines().next().unwrap_or("");
+        return Some(if interpreter.contains("python") {
+            "python"
+        } else if interpreter.contains("node") {
+            "javascript"
+        } else {
+            "shell"
+        });
+    }
+    if is_json(text) {
+        return Some("json");
+    }
+
+    let lines: Vec<&str> = text.lines().map(str::trim).filter(|line| !line.is_empty()).collect();
+    let any = |prefixes: &[&str]| lines.iter().any(|line| prefixes.iter().any(|p| line.starts_with(p)));
+
+    if any(&["fn ", "pub fn ", "pub(crate) ", "impl ", "use std::", "#[derive(", "let mut "])
+        || text.contains("-> Result<")
+        || text.contains("::new(")
+    {
+        return Some("rust");
+    }
+    if lines.iter().any(|line| {
+        (line.starts_with("def ") || line.starts_with("class ")) && line.ends_with(':')
+    }) || any(&["if __name__ ==", "from ", "elif "]) && lines.iter().any(|line| line.ends_with(':'))
+    {
+        return Some("python");
+    }
+    if any(&["#include <", "#include \"", "int main("]) {
+        return Some("c");
+    }
+    if any(&["function ", "const ", "export default ", "console.log("]) && text.contains(['(', '{']) {
+        return Some("javascript");
+    }
+    if is_yaml(&lines) {
+        return Some("yaml");
+    }
+    if lines.len() >= 2 && any(&["fi", "done", "esac", "then", "export ", "set -e"]) {
+        return Some("shell");
+    }
+    None
+}
+
+/// An object or array that parses as JSON; bare scalars don't count.
+fn is_json(text: &str) -> bool {
+    (text.starts_with('{') || text.starts_with('['))
+        && text.len() > 2
+        && serde_json::from_str::<serde_json::Value>(text).is_ok()
+}
+
+/// Every line a `key: value`, a `key:` opening a block, a `- item` or a
+/// comment. Keys are lowercase, as config files write them, and it takes
+/// three of them, or two and some nesting, so "Note: ...\nTime: ..." stays text.
+fn is_yaml(lines: &[&str]) -> bool {
+    let is_key = |line: &str| {
+        line.split_once(':').is_some_and(|(key, value)| {
+            key.starts_with(|c: char| c.is_ascii_lowercase())
+                && key.chars().all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+                && (value.is_empty() || value.starts_with(' '))
+        })
+    };
+    let is_block = |line: &str| line.ends_with(':') || line.starts_with("- ");
+    let keys = lines.iter().filter(|line| is_key(line)).count();
+    (keys >= 3 || keys >= 2 && lines.iter().any(|line| is_block(line)))
+        && lines
+            .iter()
+            .all(|line| is_key(line) || line.starts_with("- ") || line.starts_with('#') || *line == "---")
+}
+
+/// At least `MIN_STRUCTURAL_LINES` lines, of which most end in `;`, `{` or
+/// `}`, or are indented.
+fn structural(text: &str) -> bool {
+    let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+    if lines.len() < MIN_STRUCTURAL_LINES {
+        return false;
+    }
+    let punctuated = lines
+        .iter()
+        .filter(|line| line.trim_end().ends_with([';', '{', '}']))
+        .count();
+    let indented = lines.iter().filter(|line| line.starts_with([' ', '\t'])).count();
+    punctuated * 3 >= lines.len() && (punctuated + indented) * 2 >= lines.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_rust() {
+        assert_eq!(language("fn main() {\n    println!(\"hi\");\n}"), Some("rust"));
+        assert_eq!(language("use std::fs;"), Some("rust"));
+        assert_eq!(language("let v = Vec::new();"), Some("rust"));
+    }
+
+    #[test]
+    fn recognises_python() {
+        assert_eq!(language("def greet(name):\n    print(name)"), Some("python"));
+        assert_eq!(language("from os import path\nif path.exists(p):\n    pass"), Some("python"));
+        assert_eq!(language("#!/usr/bin/env python3\nprint('hi')"), Some("python"));
+    }
+
+    #[test]
+    fn recognises_json() {
+        assert_eq!(language(r#"{"name": "memoria", "tags": [1, 2]}"#), Some("json"));
+        assert_eq!(language("[1, 2, 3]"), Some("json"));
+        assert_eq!(language("[]"), None);
+        assert_eq!(language("{not json}"), None);
+    }
+
+    #[test]
+    fn recognises_yaml() {
+        assert_eq!(language("name: memoria\nversion: 1\nedition: 2021"), Some("yaml"));
+        assert_eq!(language("services:\n  web:\n    image: nginx"), Some("yaml"));
+        assert_eq!(language("deps:\n- serde\n- tokio\nfeatures: full"), Some("yaml"));
+    }
+
+    #[test]
+    fn recognises_shell() {
+        assert_eq!(language("#!/bin/sh\necho hi"), Some("shell"));
+        assert_eq!(language("#!/usr/bin/env bash\nset -e"), Some("shell"));
+        assert_eq!(language("if [ -f x ]; then\n  rm x\nfi"), Some("shell"));
+        assert_eq!(language("for f in *; do\n  echo $f\ndone"), Some("shell"));
+    }
+
+    #[test]
+    fn recognises_c_and_javascript() {
+        assert_eq!(language("#include <stdio.h>\nint main(void) { return 0; }"), Some("c"));
+        assert_eq!(language("const x = require('fs');"), Some("javascript"));
+        assert_eq!(language("function add(a, b) {\n  return a + b;\n}"), Some("javascript"));
+        assert_eq!(language("#!/usr/bin/env node\nconsole.log(1)"), Some("javascript"));
+    }
+
+    #[test]
+    fn structure_alone_marks_code_without_a_language() {
+        let snippet = "struct point {\n    int x;\n    int y;\n};";
+        assert_eq!(language(snippet), None);
+        assert!(looks_like_code(snippet));
+        assert!(!looks_like_code("a;\nb;"));
+    }
+
+    #[test]
+    fn prose_is_not_code() {
+        for text in [
+            "Hello there, see you tomorrow at five.",
+            "Note: bring snacks\nTime: 5pm",
+            "Meeting notes\n\nWe agreed to ship on Friday.\nAlice will write the docs.",
+            "TODO\n- buy milk\n- call mom\n- fix the bike",
+            "Let me know if this works for you.",
+            "From the desk of the editor:\nThanks for writing in.",
+            "Done! Then we went home.",
+        ] {
+            assert!(!looks_like_code(text), "{text:?} flagged as {:?}", language(text));
+        }
+    }
+}
@@ -1,25 +1,19 @@
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
 use tracing::{info, warn};
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Config {
     pub retention: Retention,
     pub ui: Ui,
     pub grid: Grid,
     pub behavior: Behavior,
-}
-
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            retention: Retention::default(),
-            ui: Ui::default(),
-            grid: Grid::default(),
-            behavior: Behavior::default(),
-        }
-    }
+    pub search: Search,
+    pub network: Network,
+    pub storage: Storage,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -27,13 +21,22 @@ impl Default for Config {
 pub struct Retention {
     pub days: u32,
     pub delete_unstarred_only: bool,
+    /// Seconds between cleanup runs; unset means daily. At least `MIN_RETENTION_INTERVAL_SECS`.
+    pub interval_secs: Option<u64>,
+    /// Days to keep items of a given `kind` (`image`, `text`, `url`,
+    /// `color`, `files`, `code`), overriding `days` for that kind.
+    pub per_kind: BTreeMap<String, u32>,
 }
 
+pub const MIN_RETENTION_INTERVAL_SECS: u64 = 60;
+
 impl Default for Retention {
     fn default() -> Self {
         Self {
             days: 30,
             delete_unstarred_only: true,
+            interval_secs: None,
+            per_kind: BTreeMap::new(),
         }
     }
 }
@@ -43,17 +46,85 @@ impl Default for Retention {
 pub struct Ui {
     pub width: u32,
     pub height: u32,
-    pub anchor: String,
+    /// Screen edge or corner the picker is placed at. An unknown value is
+    /// logged and replaced by the default rather than rejecting the file.
+    #[serde(deserialize_with = "anchor_or_default")]
+    pub anchor: Anchor,
     pub opacity: f32,
     pub blur: f32,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    pub const ALL: [Anchor; 9] = [
+        Anchor::TopLeft,
+        Anchor::Top,
+        Anchor::TopRight,
+        Anchor::Left,
+        Anchor::Center,
+        Anchor::Right,
+        Anchor::BottomLeft,
+        Anchor::Bottom,
+        Anchor::BottomRight,
+    ];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|a| a.as_str() == s)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Anchor::TopLeft => "top-left",
+            Anchor::Top => "top",
+            Anchor::TopRight => "top-right",
+            Anchor::Left => "left",
+            Anchor::Center => "center",
+            Anchor::Right => "right",
+            Anchor::BottomLeft => "bottom-left",
+            Anchor::Bottom => "bottom",
+            Anchor::BottomRight => "bottom-right",
+        }
+    }
+
+    /// Comma-separated list of valid values, for error messages.
+    pub fn names() -> String {
+        Self::ALL.map(Anchor::as_str).join(", ")
+    }
+}
+
+fn anchor_or_default<'de, D: serde::Deserializer<'de>>(deserializer: D) -> std::result::Result<Anchor, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Ok(Anchor::parse(&name).unwrap_or_else(|| {
+        let fallback = Ui::default().anchor;
+        warn!(
+            value = %name,
+            "ui.anchor must be one of {}, using {}",
+            Anchor::names(),
+            fallback.as_str()
+        );
+        fallback
+    }))
+}
+
 impl Default for Ui {
     fn default() -> Self {
         Self {
             width: 480,
             height: 640,
-            anchor: "top-right".to_string(),
+            anchor: Anchor::TopRight,
             opacity: 0.92,
             blur: 12.0,
         }
@@ -65,6 +136,12 @@ impl Default for Ui {
 pub struct Grid {
     pub thumb_size: u32,
     pub columns: u32,
+    /// Longest edge, in pixels, of thumbnails generated for new images.
+    pub thumb_max_px: u32,
+    /// Encoding of newly generated thumbnails.
+    pub thumb_format: ThumbFormat,
+    /// JPEG quality (1-100) used when `thumb_format` is `jpeg`.
+    pub thumb_quality: u32,
 }
 
 impl Default for Grid {
@@ -72,20 +149,620 @@ impl Default for Grid {
         Self {
             thumb_size: 104,
             columns: 3,
+            thumb_max_px: 256,
+            thumb_format: ThumbFormat::Png,
+            thumb_quality: 80,
         }
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThumbFormat {
+    Png,
+    Jpeg,
+    /// Lossless WebP; `thumb_quality` does not apply.
+    Webp,
+}
+
+impl ThumbFormat {
+    pub const ALL: [ThumbFormat; 3] = [ThumbFormat::Png, ThumbFormat::Jpeg, ThumbFormat::Webp];
+
+    pub fn parse(s: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|f| f.as_str() == s)
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ThumbFormat::Png => "png",
+            ThumbFormat::Jpeg => "jpeg",
+            ThumbFormat::Webp => "webp",
+        }
+    }
+
+    /// File extension of thumbnails written in this format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            ThumbFormat::Png => "png",
+            ThumbFormat::Jpeg => "jpg",
+            ThumbFormat::Webp => "webp",
+        }
+    }
+}
+
+/// How the watcher reads the clipboard.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum BackendKind {
+    /// Poll with `wl-paste`.
+    #[default]
+    WlPaste,
+    /// Talk `zwlr_data_control_manager_v1` directly, falling back to
+    /// `wl-paste` on compositors without it.
+    Native,
+}
+
+/// How the watcher finds out which app the focused window belongs to.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AppLookup {
+    /// Don't look; `excluded_apps` has no effect.
+    #[default]
+    None,
+    /// `class` of `hyprctl activewindow -j`.
+    Hyprland,
+    /// `app_id`, or the X11 class, of the focused node in `swaymsg -t get_tree`.
+    Sway,
+    /// Whatever `app_command` prints.
+    Command,
+}
+
 #[derive(Debug, Clone, Deserialize, Serialize)]
 #[serde(default)]
 pub struct Behavior {
     pub dedupe: bool,
+    /// With `dedupe` on, only items used within this many seconds absorb a
+    /// duplicate; older matches get a fresh entry instead. Unset means forever.
+    pub dedupe_window_secs: Option<u64>,
+    /// The same window in hours, for configs that think in days rather than
+    /// seconds; 0 means forever. Ignored when `dedupe_window_secs` is set.
+    pub dedupe_window_hours: u64,
+    /// Hash text with trailing whitespace on each line and trailing newlines
+    /// removed, so copies differing only in those count as duplicates. The
+    /// stored text is left as copied.
+    pub normalize_text: bool,
+    /// After a clipboard change, wait this long for it to settle and record
+    /// only the final state. 0 records every change the watcher sees.
+    pub debounce_ms: u32,
+    /// Shell command printing the active workspace/output name, e.g.
+    /// `hyprctl activeworkspace -j | jq -r .name`. Unset disables workspace tagging.
+    pub workspace_command: Option<String>,
+    /// Images whose perceptual hashes differ in fewer than this many bits are
+    /// treated as duplicates when `dedupe` is on. 0 disables near-duplicate matching.
+    pub phash_threshold: u32,
+    /// Command simulating a paste keystroke for the `paste` command, split on
+    /// whitespace. Unset tries `wtype` and then `ydotool`, moving on when one
+    /// is missing or fails. A configured command is the only one tried.
+    pub paste_command: Option<String>,
+    /// Delay before the paste keystroke, giving the picker time to close.
+    pub paste_delay_ms: u32,
+    /// Remove image files left behind by interrupted deletions at startup.
+    pub gc_on_start: bool,
+    /// Also record the primary (middle-click) selection. Read at startup.
+    pub watch_primary: bool,
+    /// Clipboard backend. Read at startup.
+    pub backend: BackendKind,
+    /// Further representations stored with each item when the clipboard
+    /// offers them, e.g. `text/html`; globs like `type/*` match several. `copy`
+    /// restores one when given its `mime`.
+    pub capture_mimes: Vec<String>,
+    /// Skip clipboard entries offered with one of `sensitive_mimes`, the
+    /// markers password managers put on copied secrets.
+    pub honor_sensitive_hint: bool,
+    /// Marker mimes for `honor_sensitive_hint`; globs, e.g. `type/*`.
+    pub sensitive_mimes: Vec<String>,
+    /// Mimes the watcher never records, as globs. Applies to the text, image
+    /// and file list an entry is recorded from and to `capture_mimes`.
+    pub mime_deny: Vec<String>,
+    /// When non-empty, the only mimes the watcher records, as globs;
+    /// `mime_deny` still applies.
+    pub mime_allow: Vec<String>,
+    /// How to find the focused window's app id, for `excluded_apps` and
+    /// `capture_source`.
+    pub app_lookup: AppLookup,
+    /// Shell command printing the focused app id, for `app_lookup = "command"`.
+    pub app_command: Option<String>,
+    /// App ids whose copies are never stored; `*` and `?` glob, case is
+    /// ignored. Best-effort: when the lookup fails the entry is stored.
+    pub excluded_apps: Vec<String>,
+    /// Store the focused app id with each item as `source_app`.
+    pub capture_source: bool,
+    /// Text entries shorter than this many bytes are not stored.
+    pub min_text_len: u64,
+    /// Text entries longer than this many bytes are not stored. Unset means
+    /// no limit.
+    pub max_text_len: Option<u64>,
+    /// Images larger than this many bytes are not stored. Unset means no limit.
+    pub max_image_bytes: Option<u64>,
+    /// Images wider or taller than this many pixels are scaled down to fit
+    /// before they are stored. Dedupe still goes by the copied bytes.
+    pub max_image_dimension: Option<u32>,
+    /// Store images copied as BMP, TIFF and other uncompressed formats as
+    /// PNG. JPEG, PNG, WebP and GIF are kept as copied.
+    pub normalize_images: bool,
+    /// Characters of `body` sent as `body_preview` in item listings unless the
+    /// client asks for the full body.
+    pub body_preview_chars: u32,
+    /// Keep a copy of each image in the database as well as in
+    /// `images/originals`. Off stores only the file, roughly halving image storage.
+    pub store_image_blobs: bool,
+    /// Longest title derived from a text entry, in characters, including the
+    /// `…` added when it is cut.
+    pub title_max_chars: u32,
+    /// Days `delete` keeps items in the trash, restorable with `restore`,
+    /// before the retention sweep removes them for good. Unset deletes
+    /// immediately.
+    pub trash_days: Option<u32>,
 }
 
 impl Default for Behavior {
     fn default() -> Self {
-        Self { dedupe: true }
+        Self {
+            dedupe: true,
+            dedupe_window_secs: None,
+            dedupe_window_hours: 0,
+            normalize_text: false,
+            debounce_ms: 150,
+            workspace_command: None,
+            phash_threshold: 6,
+            paste_command: None,
+            paste_delay_ms: 150,
+            gc_on_start: true,
+            watch_primary: false,
+            backend: BackendKind::WlPaste,
+            capture_mimes: vec!["text/html".to_string()],
+            honor_sensitive_hint: true,
+            sensitive_mimes: vec!["x-kde-passwordManagerHint".to_string()],
+            mime_deny: ["chromium/x-*", "application/x-qt-image", "application/x-moz-*"]
+                .map(str::to_string)
+                .to_vec(),
+            mime_allow: Vec::new(),
+            app_lookup: AppLookup::None,
+            app_command: None,
+            excluded_apps: Vec::new(),
+            capture_source: false,
+            min_text_len: 1,
+            max_text_len: None,
+            max_image_bytes: None,
+            max_image_dimension: None,
+            normalize_images: false,
+            body_preview_chars: 200,
+            store_image_blobs: true,
+            title_max_chars: 100,
+            trash_days: None,
+        }
+    }
+}
+
+impl Behavior {
+    /// The dedupe window in seconds from whichever of `dedupe_window_secs`
+    /// and `dedupe_window_hours` is set; `None` means forever.
+    pub fn dedupe_window(&self) -> Option<u64> {
+        self.dedupe_window_secs
+            .or((self.dedupe_window_hours > 0).then(|| self.dedupe_window_hours.saturating_mul(3600)))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Search {
+    /// Markers wrapped around matched terms in search snippets.
+    pub snippet_open: String,
+    pub snippet_close: String,
+    /// Maximum number of tokens in a snippet (FTS5 caps this at 64).
+    pub snippet_tokens: u32,
+}
+
+impl Default for Search {
+    fn default() -> Self {
+        Self {
+            snippet_open: "<b>".to_string(),
+            snippet_close: "</b>".to_string(),
+            snippet_tokens: 12,
+        }
+    }
+}
+
+/// Optional TCP listener for remote clients. Traffic is unencrypted.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Network {
+    /// `host:port` to listen on, e.g. `192.168.1.10:7878`. Unset disables TCP.
+    pub tcp_listen: Option<String>,
+    /// Pre-shared token every TCP client must send as its first line. TCP
+    /// stays disabled while this is unset.
+    pub token: Option<String>,
+}
+
+/// SQLite settings, applied when the database is opened at startup.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Storage {
+    pub journal_mode: JournalMode,
+    /// Unset keeps SQLite's default (`full`).
+    pub synchronous: Option<Synchronous>,
+}
+
+/// Values of `PRAGMA journal_mode` the daemon accepts. `memory` and `off`
+/// are left out: a crash mid-write would corrupt the history.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum JournalMode {
+    #[default]
+    Wal,
+    /// Rollback journal; for filesystems where WAL's shared memory doesn't
+    /// work, such as network mounts.
+    Delete,
+    Truncate,
+    Persist,
+}
+
+impl JournalMode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JournalMode::Wal => "wal",
+            JournalMode::Delete => "delete",
+            JournalMode::Truncate => "truncate",
+            JournalMode::Persist => "persist",
+        }
+    }
+}
+
+/// Values of `PRAGMA synchronous`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    Normal,
+    Full,
+    Extra,
+}
+
+impl Synchronous {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Synchronous::Off => "off",
+            Synchronous::Normal => "normal",
+            Synchronous::Full => "full",
+            Synchronous::Extra => "extra",
+        }
+    }
+}
+
+impl Config {
+    /// Returns a copy of this config with a partial settings object applied.
+    ///
+    /// The patch mirrors the `get_settings` shape, e.g. `{"ui":{"opacity":0.85}}`.
+    /// Every field is validated before anything is changed, and all offending
+    /// fields are reported together.
+    pub fn merged_with(&self, patch: &serde_json::Map<String, Value>) -> Result<Config> {
+        let mut cfg = self.clone();
+        let mut errors: Vec<String> = Vec::new();
+
+        for (section, fields) in patch {
+            let Some(fields) = fields.as_object() else {
+                errors.push(format!("{section} must be an object"));
+                continue;
+            };
+
+            for (key, value) in fields {
+                let name = format!("{section}.{key}");
+                let res = match (section.as_str(), key.as_str()) {
+                    ("retention", "days") => set_u32(&mut cfg.retention.days, value, 1, 36_500),
+                    ("retention", "delete_unstarred_only") => set_bool(&mut cfg.retention.delete_unstarred_only, value),
+                    ("retention", "interval_secs") => set_optional(&mut cfg.retention.interval_secs, value, |field, value| {
+                        set_u64(field, value, MIN_RETENTION_INTERVAL_SECS)
+                    }),
+                    ("retention", "per_kind") => set_per_kind(&mut cfg.retention.per_kind, value),
+                    ("ui", "width") => set_u32(&mut cfg.ui.width, value, 1, 10_000),
+                    ("ui", "height") => set_u32(&mut cfg.ui.height, value, 1, 10_000),
+                    ("ui", "anchor") => value
+                        .as_str()
+                        .and_then(Anchor::parse)
+                        .map(|a| cfg.ui.anchor = a)
+                        .ok_or_else(|| format!("must be one of {}", Anchor::names())),
+                    ("ui", "opacity") => set_f32(&mut cfg.ui.opacity, value, 0.0, 1.0),
+                    ("ui", "blur") => set_f32(&mut cfg.ui.blur, value, 0.0, 100.0),
+                    ("grid", "thumb_size") => set_u32(&mut cfg.grid.thumb_size, value, 16, 1024),
+                    ("grid", "columns") => set_u32(&mut cfg.grid.columns, value, 1, 32),
+                    ("grid", "thumb_max_px") => set_u32(&mut cfg.grid.thumb_max_px, value, 32, 1024),
+                    ("grid", "thumb_format") => value
+                        .as_str()
+                        .and_then(ThumbFormat::parse)
+                        .map(|f| cfg.grid.thumb_format = f)
+                        .ok_or_else(|| "must be one of png, jpeg, webp".to_string()),
+                    ("grid", "thumb_quality") => set_u32(&mut cfg.grid.thumb_quality, value, 1, 100),
+                    ("behavior", "dedupe") => set_bool(&mut cfg.behavior.dedupe, value),
+                    // `get_settings` reports the effective window, so setting
+                    // it replaces `dedupe_window_hours` too.
+                    ("behavior", "dedupe_window_secs") => {
+                        cfg.behavior.dedupe_window_hours = 0;
+                        set_optional(&mut cfg.behavior.dedupe_window_secs, value, |field, value| set_u64(field, value, 1))
+                    }
+                    ("behavior", "trash_days") => {
+                        set_optional(&mut cfg.behavior.trash_days, value, |field, value| set_u32(field, value, 1, 36_500))
+                    }
+                    ("behavior", "min_text_len") => set_u64(&mut cfg.behavior.min_text_len, value, 0),
+                    ("behavior", "max_text_len") => {
+                        set_optional(&mut cfg.behavior.max_text_len, value, |field, value| set_u64(field, value, 1))
+                    }
+                    _ => Err("unknown setting".to_string()),
+                };
+
+                if let Err(msg) = res {
+                    errors.push(format!("{name}: {msg}"));
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            anyhow::bail!("{}", errors.join("; "));
+        }
+
+        // The per-field ranges above should make this unreachable; it keeps a
+        // bad config from ever being saved.
+        cfg.validate()?;
+        Ok(cfg)
+    }
+
+    /// Checks every field in `FIELD_CHECKS`, reporting all offenders together.
+    pub fn validate(&self) -> Result<()> {
+        let errors: Vec<String> = FIELD_CHECKS
+            .iter()
+            .filter(|check| !(check.valid)(self))
+            .map(|check| format!("{} must be {}", check.name, check.expected))
+            .collect();
+        if !errors.is_empty() {
+            anyhow::bail!("{}", errors.join("; "));
+        }
+        Ok(())
+    }
+
+    /// Resets each field that fails validation, logging it, and leaves the
+    /// rest of the config alone.
+    fn fix_invalid_fields(&mut self) {
+        let current = serde_json::to_value(&*self).unwrap_or_default();
+        for check in FIELD_CHECKS {
+            if (check.valid)(self) {
+                continue;
+            }
+            let value = current
+                .pointer(&format!("/{}", check.name.replace('.', "/")))
+                .cloned()
+                .unwrap_or_default();
+            warn!(
+                field = check.name,
+                value = %value,
+                "{} must be {}, using {}",
+                check.name,
+                check.expected,
+                check.fallback
+            );
+            (check.reset)(self);
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let toml_string = toml::to_string_pretty(self)
+            .context("failed to serialize config")?;
+
+        std::fs::write(path, toml_string)
+            .with_context(|| format!("failed to write config: {}", path.display()))?;
+
+        info!("saved config to: {}", path.display());
+        Ok(())
+    }
+}
+
+/// A constraint on one config field and how to repair a value that breaks it.
+struct FieldCheck {
+    /// Dotted path, e.g. `ui.opacity`.
+    name: &'static str,
+    /// Completes "must be ...".
+    expected: &'static str,
+    /// What `reset` falls back to, for the log.
+    fallback: &'static str,
+    valid: fn(&Config) -> bool,
+    reset: fn(&mut Config),
+}
+
+fn is_item_kind(kind: &str) -> bool {
+    kind == "image" || crate::clipboard::TEXT_KINDS.contains(&kind)
+}
+
+/// Fields whose type admits values the daemon or the picker can't use.
+/// Ranges match the ones `merged_with` enforces.
+const FIELD_CHECKS: &[FieldCheck] = &[
+    FieldCheck {
+        name: "retention.days",
+        expected: "at least 1",
+        fallback: "the default",
+        valid: |c| c.retention.days >= 1,
+        reset: |c| c.retention.days = Retention::default().days,
+    },
+    FieldCheck {
+        name: "retention.interval_secs",
+        expected: "at least 60",
+        fallback: "the minimum",
+        valid: |c| c.retention.interval_secs.is_none_or(|s| s >= MIN_RETENTION_INTERVAL_SECS),
+        reset: |c| c.retention.interval_secs = Some(MIN_RETENTION_INTERVAL_SECS),
+    },
+    FieldCheck {
+        name: "retention.per_kind",
+        expected: "a map from a known kind to at least 1 day",
+        fallback: "retention.days for the offending kinds",
+        valid: |c| c.retention.per_kind.iter().all(|(kind, days)| is_item_kind(kind) && *days >= 1),
+        reset: |c| c.retention.per_kind.retain(|kind, days| is_item_kind(kind) && *days >= 1),
+    },
+    FieldCheck {
+        name: "behavior.dedupe_window_hours",
+        expected: "0 when behavior.dedupe_window_secs is set",
+        fallback: "dedupe_window_secs",
+        valid: |c| c.behavior.dedupe_window_secs.is_none() || c.behavior.dedupe_window_hours == 0,
+        reset: |c| c.behavior.dedupe_window_hours = 0,
+    },
+    FieldCheck {
+        name: "behavior.app_command",
+        expected: "set when behavior.app_lookup is \"command\"",
+        fallback: "app_lookup = \"none\"",
+        valid: |c| c.behavior.app_lookup != AppLookup::Command || c.behavior.app_command.is_some(),
+        reset: |c| c.behavior.app_lookup = AppLookup::None,
+    },
+    FieldCheck {
+        name: "behavior.max_text_len",
+        expected: "at least behavior.min_text_len",
+        fallback: "no limit",
+        valid: |c| c.behavior.max_text_len.is_none_or(|max| max >= c.behavior.min_text_len),
+        reset: |c| c.behavior.max_text_len = None,
+    },
+    FieldCheck {
+        name: "behavior.max_image_dimension",
+        expected: "at least 1",
+        fallback: "no limit",
+        valid: |c| c.behavior.max_image_dimension.is_none_or(|max| max >= 1),
+        reset: |c| c.behavior.max_image_dimension = None,
+    },
+    FieldCheck {
+        name: "ui.width",
+        expected: "between 1 and 10000",
+        fallback: "the default",
+        valid: |c| (1..=10_000).contains(&c.ui.width),
+        reset: |c| c.ui.width = Ui::default().width,
+    },
+    FieldCheck {
+        name: "ui.height",
+        expected: "between 1 and 10000",
+        fallback: "the default",
+        valid: |c| (1..=10_000).contains(&c.ui.height),
+        reset: |c| c.ui.height = Ui::default().height,
+    },
+    FieldCheck {
+        name: "ui.opacity",
+        expected: "between 0.0 and 1.0",
+        fallback: "the default",
+        valid: |c| (0.0..=1.0).contains(&c.ui.opacity),
+        reset: |c| c.ui.opacity = Ui::default().opacity,
+    },
+    FieldCheck {
+        name: "ui.blur",
+        expected: "between 0 and 100",
+        fallback: "the default",
+        valid: |c| (0.0..=100.0).contains(&c.ui.blur),
+        reset: |c| c.ui.blur = Ui::default().blur,
+    },
+    FieldCheck {
+        name: "behavior.title_max_chars",
+        expected: "between 8 and 1000",
+        fallback: "the default",
+        valid: |c| (8..=1000).contains(&c.behavior.title_max_chars),
+        reset: |c| c.behavior.title_max_chars = Behavior::default().title_max_chars,
+    },
+    FieldCheck {
+        name: "grid.thumb_size",
+        expected: "between 16 and 1024",
+        fallback: "the default",
+        valid: |c| (16..=1024).contains(&c.grid.thumb_size),
+        reset: |c| c.grid.thumb_size = Grid::default().thumb_size,
+    },
+    FieldCheck {
+        name: "grid.columns",
+        expected: "between 1 and 32",
+        fallback: "the default",
+        valid: |c| (1..=32).contains(&c.grid.columns),
+        reset: |c| c.grid.columns = Grid::default().columns,
+    },
+    FieldCheck {
+        name: "grid.thumb_max_px",
+        expected: "between 32 and 1024",
+        fallback: "the default",
+        valid: |c| (32..=1024).contains(&c.grid.thumb_max_px),
+        reset: |c| c.grid.thumb_max_px = Grid::default().thumb_max_px,
+    },
+    FieldCheck {
+        name: "grid.thumb_quality",
+        expected: "between 1 and 100",
+        fallback: "the default",
+        valid: |c| (1..=100).contains(&c.grid.thumb_quality),
+        reset: |c| c.grid.thumb_quality = Grid::default().thumb_quality,
+    },
+];
+
+fn set_u32(field: &mut u32, value: &Value, min: u32, max: u32) -> std::result::Result<(), String> {
+    let n = value
+        .as_u64()
+        .ok_or_else(|| "must be a non-negative integer".to_string())?;
+    if n < min as u64 || n > max as u64 {
+        return Err(format!("must be between {min} and {max}"));
     }
+    *field = n as u32;
+    Ok(())
+}
+
+fn set_u64(field: &mut u64, value: &Value, min: u64) -> std::result::Result<(), String> {
+    let n = value
+        .as_u64()
+        .ok_or_else(|| "must be a non-negative integer".to_string())?;
+    if n < min {
+        return Err(format!("must be at least {min}"));
+    }
+    *field = n;
+    Ok(())
+}
+
+fn set_bool(field: &mut bool, value: &Value) -> std::result::Result<(), String> {
+    *field = value.as_bool().ok_or_else(|| "must be a boolean".to_string())?;
+    Ok(())
+}
+
+/// `null` unsets `field`; anything else goes through `set`.
+fn set_optional<T: Default>(
+    field: &mut Option<T>,
+    value: &Value,
+    set: impl FnOnce(&mut T, &Value) -> std::result::Result<(), String>,
+) -> std::result::Result<(), String> {
+    if value.is_null() {
+        *field = None;
+        return Ok(());
+    }
+    let mut inner = T::default();
+    set(&mut inner, value)?;
+    *field = Some(inner);
+    Ok(())
+}
+
+/// Replaces the whole map, as `get_settings` reports it.
+fn set_per_kind(field: &mut BTreeMap<String, u32>, value: &Value) -> std::result::Result<(), String> {
+    let expected = || "must map a known kind to at least 1 day".to_string();
+    let map = value.as_object().ok_or_else(expected)?;
+    let mut per_kind = BTreeMap::new();
+    for (kind, days) in map {
+        let days = days.as_u64().filter(|days| (1..=36_500).contains(days)).ok_or_else(expected)?;
+        if !is_item_kind(kind) {
+            return Err(expected());
+        }
+        per_kind.insert(kind.clone(), days as u32);
+    }
+    *field = per_kind;
+    Ok(())
+}
+
+fn set_f32(field: &mut f32, value: &Value, min: f32, max: f32) -> std::result::Result<(), String> {
+    let n = value.as_f64().ok_or_else(|| "must be a number".to_string())? as f32;
+    if !(min..=max).contains(&n) {
+        return Err(format!("must be between {min} and {max}"));
+    }
+    *field = n;
+    Ok(())
 }
 
 pub fn default_config_path() -> Result<PathBuf> {
@@ -93,6 +770,53 @@ pub fn default_config_path() -> Result<PathBuf> {
     Ok(home.join(".config/memoria/config.toml"))
 }
 
+/// Applies the keys of `value` one at a time over the defaults, so a key of
+/// the wrong type (`columns = -1`, `opacity = "high"`) falls back on its own
+/// instead of taking the rest of the file with it.
+fn lenient_from_value(value: toml::Value) -> Result<Config> {
+    let mut merged = toml::Value::try_from(Config::default()).context("failed to serialize default config")?;
+    let toml::Value::Table(sections) = value else {
+        return Ok(Config::default());
+    };
+
+    for (section, fields) in sections {
+        let entries: Vec<(Option<String>, toml::Value)> = match fields {
+            toml::Value::Table(fields) => fields.into_iter().map(|(key, field)| (Some(key), field)).collect(),
+            other => vec![(None, other)],
+        };
+        for (key, field) in entries {
+            let mut candidate = merged.clone();
+            let Some(root) = candidate.as_table_mut() else {
+                continue;
+            };
+            match &key {
+                Some(key) => {
+                    let table = root
+                        .entry(section.as_str())
+                        .or_insert_with(|| toml::Value::Table(toml::Table::new()));
+                    match table.as_table_mut() {
+                        Some(table) => {
+                            table.insert(key.clone(), field);
+                        }
+                        None => continue,
+                    }
+                }
+                None => {
+                    root.insert(section.clone(), field);
+                }
+            }
+
+            let name = key.map_or_else(|| section.clone(), |key| format!("{section}.{key}"));
+            match candidate.clone().try_into::<Config>() {
+                Ok(_) => merged = candidate,
+                Err(err) => warn!(field = name.as_str(), error = %err, "invalid config value, using the default"),
+            }
+        }
+    }
+
+    merged.try_into().context("failed to apply config values")
+}
+
 pub fn load_or_default(path: &Path) -> Result<Config> {
     if !path.exists() {
         warn!("config file not found, creating with defaults: {}", path.display());
@@ -117,8 +841,9 @@ pub fn load_or_default(path: &Path) -> Result<Config> {
         .with_context(|| format!("failed to read config file: {}", path.display()))?;
 
     match toml::from_str::<Config>(&raw) {
-        Ok(cfg) => {
+        Ok(mut cfg) => {
             info!("loaded config from: {}", path.display());
+            cfg.fix_invalid_fields();
             Ok(cfg)
         }
         Err(err) => {
@@ -127,10 +852,12 @@ pub fn load_or_default(path: &Path) -> Result<Config> {
                 Ok(Config::default())
             } else {
                 match toml::from_str::<toml::Value>(&raw) {
-                    Ok(_) => {
+                    Ok(value) => {
                         warn!("config file has missing or invalid fields, using defaults where needed");
                         warn!("parse warning: {}", err);
-                        Ok(Config::default())
+                        let mut cfg = lenient_from_value(value)?;
+                        cfg.fix_invalid_fields();
+                        Ok(cfg)
                     }
                     Err(_) => {
                         Err(anyhow::anyhow!(
@@ -149,3 +876,221 @@ pub fn load_or_default(path: &Path) -> Result<Config> {
 pub fn load_from_file(path: &Path) -> Result<Config> {
     load_or_default(path)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn patch(value: Value) -> serde_json::Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    /// Loads `toml` through `load_or_default`, as the daemon does at startup.
+    fn load(toml: &str) -> Config {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "memoria-config-test-{}-{}.toml",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::write(&path, toml).unwrap();
+        let cfg = load_or_default(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        cfg
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Config::default().validate().unwrap();
+    }
+
+    #[test]
+    fn validate_accepts_the_boundaries() {
+        let mut cfg = Config::default();
+        cfg.ui.opacity = 0.0;
+        cfg.ui.blur = 100.0;
+        cfg.ui.width = 1;
+        cfg.ui.height = 10_000;
+        cfg.grid.columns = 32;
+        cfg.grid.thumb_size = 16;
+        cfg.grid.thumb_quality = 1;
+        cfg.retention.days = 1;
+        cfg.retention.interval_secs = Some(MIN_RETENTION_INTERVAL_SECS);
+        cfg.validate().unwrap();
+        cfg.ui.opacity = 1.0;
+        cfg.grid.columns = 1;
+        cfg.validate().unwrap();
+    }
+
+    #[test]
+    fn validate_rejects_values_just_outside() {
+        type Break = fn(&mut Config);
+        let cases: [(&str, Break); 9] = [
+            ("ui.opacity", |c| c.ui.opacity = 1.01),
+            ("ui.opacity", |c| c.ui.opacity = -0.01),
+            ("ui.width", |c| c.ui.width = 0),
+            ("ui.height", |c| c.ui.height = 10_001),
+            ("grid.columns", |c| c.grid.columns = 0),
+            ("grid.columns", |c| c.grid.columns = 33),
+            ("grid.thumb_quality", |c| c.grid.thumb_quality = 101),
+            ("retention.days", |c| c.retention.days = 0),
+            ("retention.interval_secs", |c| c.retention.interval_secs = Some(59)),
+        ];
+        for (field, break_it) in cases {
+            let mut cfg = Config::default();
+            break_it(&mut cfg);
+            let err = cfg.validate().unwrap_err().to_string();
+            assert!(err.contains(field), "{field} not reported in {err}");
+        }
+    }
+
+    #[test]
+    fn loading_resets_only_the_offending_fields() {
+        let cfg = load(
+            r#"
+            [ui]
+            opacity = 5.0
+            width = 800
+            anchor = "upside-down"
+
+            [grid]
+            columns = 0
+            thumb_size = 64
+
+            [retention]
+            days = 0
+            delete_unstarred_only = false
+            "#,
+        );
+        assert_eq!(cfg.ui.opacity, Ui::default().opacity);
+        assert_eq!(cfg.ui.width, 800);
+        assert_eq!(cfg.ui.anchor, Ui::default().anchor);
+        assert_eq!(cfg.grid.columns, Grid::default().columns);
+        assert_eq!(cfg.grid.thumb_size, 64);
+        assert_eq!(cfg.retention.days, Retention::default().days);
+        assert!(!cfg.retention.delete_unstarred_only);
+        cfg.validate().unwrap();
+    }
+
+    #[test]
+    fn loading_resets_only_fields_of_the_wrong_type() {
+        let cfg = load(
+            r#"
+            [ui]
+            opacity = "high"
+            width = 640
+
+            [grid]
+            columns = -1
+            thumb_size = 96
+
+            [behavior]
+            dedupe = "sometimes"
+            paste_delay_ms = 40
+            "#,
+        );
+        assert_eq!(cfg.ui.opacity, Ui::default().opacity);
+        assert_eq!(cfg.ui.width, 640);
+        assert_eq!(cfg.grid.columns, Grid::default().columns);
+        assert_eq!(cfg.grid.thumb_size, 96);
+        assert_eq!(cfg.behavior.dedupe, Behavior::default().dedupe);
+        assert_eq!(cfg.behavior.paste_delay_ms, 40);
+    }
+
+    #[test]
+    fn known_anchors_load() {
+        for anchor in Anchor::ALL {
+            let cfg = load(&format!("[ui]\nanchor = \"{}\"\n", anchor.as_str()));
+            assert_eq!(cfg.ui.anchor, anchor);
+        }
+    }
+
+    #[test]
+    fn dedupe_window_prefers_seconds_over_hours() {
+        let mut behavior = Behavior::default();
+        assert_eq!(behavior.dedupe_window(), None);
+        behavior.dedupe_window_hours = 2;
+        assert_eq!(behavior.dedupe_window(), Some(7200));
+        behavior.dedupe_window_secs = Some(60);
+        assert_eq!(behavior.dedupe_window(), Some(60));
+        behavior.dedupe_window_secs = Some(0);
+        assert_eq!(behavior.dedupe_window(), Some(0));
+    }
+
+    #[test]
+    fn merged_with_sets_retention_and_behavior_fields() {
+        let cfg = Config::default()
+            .merged_with(&patch(json!({
+                "retention": {
+                    "days": 7,
+                    "delete_unstarred_only": false,
+                    "interval_secs": 3600,
+                    "per_kind": { "image": 2 }
+                },
+                "behavior": {
+                    "dedupe_window_secs": 120,
+                    "trash_days": 14,
+                    "min_text_len": 3,
+                    "max_text_len": 1000
+                }
+            })))
+            .unwrap();
+        assert_eq!(cfg.retention.days, 7);
+        assert!(!cfg.retention.delete_unstarred_only);
+        assert_eq!(cfg.retention.interval_secs, Some(3600));
+        assert_eq!(cfg.retention.per_kind, BTreeMap::from([("image".to_string(), 2)]));
+        assert_eq!(cfg.behavior.dedupe_window(), Some(120));
+        assert_eq!(cfg.behavior.trash_days, Some(14));
+        assert_eq!(cfg.behavior.min_text_len, 3);
+        assert_eq!(cfg.behavior.max_text_len, Some(1000));
+    }
+
+    #[test]
+    fn merged_with_null_unsets_optional_fields() {
+        let mut cfg = Config::default();
+        cfg.behavior.trash_days = Some(3);
+        cfg.behavior.dedupe_window_hours = 2;
+        let cfg = cfg
+            .merged_with(&patch(json!({
+                "retention": { "interval_secs": null },
+                "behavior": { "trash_days": null, "dedupe_window_secs": null, "max_text_len": null }
+            })))
+            .unwrap();
+        assert_eq!(cfg.retention.interval_secs, None);
+        assert_eq!(cfg.behavior.trash_days, None);
+        assert_eq!(cfg.behavior.dedupe_window(), None);
+        assert_eq!(cfg.behavior.max_text_len, None);
+    }
+
+    #[test]
+    fn merged_with_reports_every_bad_field() {
+        let err = Config::default()
+            .merged_with(&patch(json!({
+                "retention": { "days": 0, "interval_secs": 5, "per_kind": { "sound": 3 } },
+                "ui": { "opacity": 2.0 },
+                "behavior": { "dedupe": "yes", "colour": true }
+            })))
+            .unwrap_err()
+            .to_string();
+        for field in [
+            "retention.days",
+            "retention.interval_secs",
+            "retention.per_kind",
+            "ui.opacity",
+            "behavior.dedupe",
+            "behavior.colour: unknown setting",
+        ] {
+            assert!(err.contains(field), "{field} missing from {err}");
+        }
+    }
+
+    #[test]
+    fn merged_with_checks_fields_against_each_other() {
+        let err = Config::default()
+            .merged_with(&patch(json!({ "behavior": { "min_text_len": 10, "max_text_len": 5 } })))
+            .unwrap_err();
+        assert!(err.to_string().contains("behavior.max_text_len"));
+    }
+}
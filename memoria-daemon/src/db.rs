@@ -1,23 +1,56 @@
 use anyhow::{Context, Result};
 use rusqlite::{params, Connection};
 use std::path::{Path, PathBuf};
+use tracing::{info, warn};
 
+use crate::config::Storage;
+
+type Migration = fn(&Connection) -> Result<()>;
+
+/// Ordered schema migrations; entry `i` upgrades a database to version `i + 1`.
+/// Append new steps at the end and never edit an existing one.
+const MIGRATIONS: &[Migration] = &[
+    migrate_base_schema,
+    migrate_workspace_column,
+    migrate_kind_column,
+    migrate_image_phash,
+    migrate_sort_order,
+    migrate_position,
+    migrate_selection,
+    migrate_image_dimensions,
+    migrate_tags,
+    migrate_non_unique_hash,
+    migrate_usage_log,
+    migrate_image_path,
+    migrate_notes,
+    migrate_trash,
+    migrate_fts_unicode61,
+    migrate_image_byte_size,
+    migrate_item_contents,
+    migrate_source_app,
+    migrate_raw_text,
+    migrate_language,
+];
+
+/// Schema version written to `PRAGMA user_version` by this binary.
+pub const SCHEMA_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// `~/.local/share/memoria`, used unless `--data-dir` says otherwise.
 pub fn default_data_dir() -> Result<PathBuf> {
     let home = dirs::home_dir().context("could not resolve home directory")?;
     Ok(home.join(".local/share/memoria"))
 }
 
-pub fn default_db_path() -> Result<PathBuf> {
-    Ok(default_data_dir()?.join("memoria.db"))
-}
-
 pub fn ensure_data_dir(dir: &Path) -> Result<()> {
     std::fs::create_dir_all(dir)
         .with_context(|| format!("failed to create data dir: {}", dir.display()))?;
     Ok(())
 }
 
-pub fn open_and_init(db_path: &Path) -> Result<Connection> {
+/// Prepared statements kept per connection by `prepare_cached`.
+const STATEMENT_CACHE_CAPACITY: usize = 64;
+
+pub fn open_and_init(db_path: &Path, storage: &Storage) -> Result<Connection> {
     if let Some(parent) = db_path.parent() {
         if parent.exists() {
             let metadata = std::fs::metadata(parent)
@@ -33,9 +66,84 @@ pub fn open_and_init(db_path: &Path) -> Result<Connection> {
 
     conn.pragma_update(None, "foreign_keys", "ON")
         .context("failed to enable foreign_keys pragma")?;
-    conn.pragma_update(None, "journal_mode", "WAL")
-        .context("failed to enable WAL mode")?;
+    // SQLite answers with the mode it ended up in; it keeps the old one when
+    // the filesystem can't do the requested one.
+    let journal_mode: String = conn
+        .pragma_update_and_check(None, "journal_mode", storage.journal_mode.as_str(), |row| row.get(0))
+        .with_context(|| format!("failed to set journal_mode to {}", storage.journal_mode.as_str()))?;
+    if !journal_mode.eq_ignore_ascii_case(storage.journal_mode.as_str()) {
+        warn!(requested = storage.journal_mode.as_str(), actual=%journal_mode, "database journal mode not applied");
+    }
+    if let Some(synchronous) = storage.synchronous {
+        conn.pragma_update(None, "synchronous", synchronous.as_str())
+            .with_context(|| format!("failed to set synchronous to {}", synchronous.as_str()))?;
+    }
+
+    run_migrations(&conn)?;
+
+    // Listings build their SQL from the request's filters; keep a statement
+    // for each combination a polling UI cycles through.
+    conn.set_prepared_statement_cache_capacity(STATEMENT_CACHE_CAPACITY);
+
+    let _: i64 = conn.query_row("SELECT 1", params![], |row| row.get(0))
+        .context("database connection sanity check failed")?;
+
+    Ok(conn)
+}
+
+/// Appends a use of `item_id` at `used_at` to `usage_log`.
+pub fn record_usage(conn: &Connection, item_id: i64, used_at: i64) -> Result<()> {
+    conn.execute("INSERT INTO usage_log (item_id, used_at) VALUES (?, ?)", [item_id, used_at])
+        .context("failed to record usage")?;
+    Ok(())
+}
+
+fn run_migrations(conn: &Connection) -> Result<()> {
+    let current: u32 = conn
+        .pragma_query_value(None, "user_version", |row| row.get(0))
+        .context("failed to read schema version")?;
+
+    if current > SCHEMA_VERSION {
+        anyhow::bail!(
+            "database schema version {current} is newer than this build supports ({SCHEMA_VERSION}); upgrade memoria"
+        );
+    }
+
+    // Table rebuilds drop and recreate `items`; with foreign keys enforced that
+    // would cascade into `images` and `tags`. The pragma is a no-op inside a
+    // transaction, so it is toggled around the whole run and each step is
+    // checked with `foreign_key_check` instead.
+    conn.pragma_update(None, "foreign_keys", "OFF")
+        .context("failed to disable foreign_keys pragma")?;
+
+    for (idx, migration) in MIGRATIONS.iter().enumerate().skip(current as usize) {
+        let version = idx as u32 + 1;
+        let tx = conn.unchecked_transaction()?;
+        migration(&tx).with_context(|| format!("failed to apply schema migration {version}"))?;
+        check_foreign_keys(&tx).with_context(|| format!("schema migration {version} broke foreign keys"))?;
+        tx.pragma_update(None, "user_version", version)
+            .context("failed to record schema version")?;
+        tx.commit()?;
+        info!(version, "applied schema migration");
+    }
+
+    conn.pragma_update(None, "foreign_keys", "ON")
+        .context("failed to enable foreign_keys pragma")?;
+
+    Ok(())
+}
+
+fn check_foreign_keys(conn: &Connection) -> Result<()> {
+    let violations: i64 = conn.query_row("SELECT COUNT(*) FROM pragma_foreign_key_check", [], |row| row.get(0))?;
+    if violations > 0 {
+        anyhow::bail!("{violations} foreign key violations");
+    }
+    Ok(())
+}
 
+/// v1: the original schema. Uses `IF NOT EXISTS` so databases created before
+/// versioning existed upgrade cleanly.
+fn migrate_base_schema(conn: &Connection) -> Result<()> {
     conn.execute_batch(
         r#"
         CREATE TABLE IF NOT EXISTS items (
@@ -84,8 +192,386 @@ pub fn open_and_init(db_path: &Path) -> Result<Connection> {
     )
     .context("failed to initialize database schema - database may be corrupted")?;
 
-    let _: i64 = conn.query_row("SELECT 1", params![], |row| row.get(0))
-        .context("database connection sanity check failed")?;
+    Ok(())
+}
 
-    Ok(conn)
+/// v2: workspace the item was captured on.
+fn migrate_workspace_column(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "items", "workspace", "TEXT")
+}
+
+/// v3: content kind (`text`, `url`, `color`, `image`). Existing rows are
+/// backfilled as `image` or `text` only; they are not re-classified.
+fn migrate_kind_column(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "items", "kind", "TEXT")?;
+    conn.execute_batch(
+        r#"
+        UPDATE items SET kind = 'image'
+            WHERE kind IS NULL AND EXISTS (SELECT 1 FROM images WHERE images.item_id = items.id);
+        UPDATE items SET kind = 'text' WHERE kind IS NULL;
+        "#,
+    )
+    .context("failed to backfill item kinds")?;
+    Ok(())
+}
+
+/// v4: perceptual hash of each image for near-duplicate detection.
+fn migrate_image_phash(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "images", "phash", "INTEGER")
+}
+
+/// v5: manual ordering of starred (pinned) items; NULL falls back to recency.
+fn migrate_sort_order(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "items", "sort_order", "INTEGER")
+}
+
+/// v6: fixed list position pinning an item above everything else; NULL means
+/// chronological ordering.
+fn migrate_position(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "items", "position", "INTEGER")
+}
+
+/// v7: Wayland selection an item was captured from (`clipboard` or `primary`).
+fn migrate_selection(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "items", "selection", "TEXT NOT NULL DEFAULT 'clipboard'")
+}
+
+/// v8: pixel dimensions of each image; NULL for images stored before this version.
+fn migrate_image_dimensions(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "images", "width", "INTEGER")?;
+    add_column_if_missing(conn, "images", "height", "INTEGER")
+}
+
+/// v9: free-form labels on items, normalized to trimmed lowercase.
+fn migrate_tags(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS tags (
+            item_id       INTEGER NOT NULL,
+            tag           TEXT NOT NULL,
+            PRIMARY KEY(item_id, tag),
+            FOREIGN KEY(item_id) REFERENCES items(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS tags_tag ON tags(tag);
+        "#,
+    )
+    .context("failed to create tags table")?;
+    Ok(())
+}
+
+/// v10: drops `UNIQUE(hash)` so a duplicate outside `dedupe_window_secs` (or
+/// any duplicate with `dedupe` off) gets its own row. SQLite cannot drop a
+/// constraint in place, so `items` is rebuilt with the same ids and the FTS
+/// triggers are recreated; `items_fts` keys on rowid and stays valid. Items
+/// sharing a hash also share image files on disk, so file cleanup must check
+/// `retention::hash_in_use` first.
+fn migrate_non_unique_hash(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE items_new (
+            id            INTEGER PRIMARY KEY,
+            created_at    INTEGER NOT NULL,
+            updated_at    INTEGER NOT NULL,
+            last_used     INTEGER,
+            starred       INTEGER DEFAULT 0,
+            title         TEXT,
+            body          TEXT,
+            hash          TEXT,
+            workspace     TEXT,
+            kind          TEXT,
+            sort_order    INTEGER,
+            position      INTEGER,
+            selection     TEXT NOT NULL DEFAULT 'clipboard'
+        );
+
+        INSERT INTO items_new (id, created_at, updated_at, last_used, starred, title, body, hash,
+                               workspace, kind, sort_order, position, selection)
+            SELECT id, created_at, updated_at, last_used, starred, title, body, hash,
+                   workspace, kind, sort_order, position, selection
+            FROM items;
+
+        DROP TABLE items;
+        ALTER TABLE items_new RENAME TO items;
+
+        CREATE INDEX items_hash ON items(hash);
+
+        CREATE TRIGGER items_ai AFTER INSERT ON items BEGIN
+            INSERT INTO items_fts(rowid, title, body) VALUES (new.id, new.title, new.body);
+        END;
+
+        CREATE TRIGGER items_ad AFTER DELETE ON items BEGIN
+            INSERT INTO items_fts(items_fts, rowid, title, body) VALUES('delete', old.id, old.title, old.body);
+        END;
+
+        CREATE TRIGGER items_au AFTER UPDATE ON items BEGIN
+            INSERT INTO items_fts(items_fts, rowid, title, body) VALUES('delete', old.id, old.title, old.body);
+            INSERT INTO items_fts(rowid, title, body) VALUES (new.id, new.title, new.body);
+        END;
+        "#,
+    )
+    .context("failed to rebuild items without UNIQUE(hash)")?;
+    Ok(())
+}
+
+/// v11: one row per use of an item (copy, touch or dedupe hit). Rows go
+/// with their item.
+fn migrate_usage_log(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS usage_log (
+            item_id       INTEGER NOT NULL,
+            used_at       INTEGER NOT NULL,
+            FOREIGN KEY(item_id) REFERENCES items(id) ON DELETE CASCADE
+        );
+        CREATE INDEX IF NOT EXISTS usage_log_item ON usage_log(item_id, used_at);
+        "#,
+    )
+    .context("failed to create usage_log table")?;
+    Ok(())
+}
+
+/// v12: path of the original image file. `bytes` may be NULL when
+/// `behavior.store_image_blobs` is off, leaving the file as the only copy.
+fn migrate_image_path(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "images", "path", "TEXT")
+}
+
+/// v13: free-text note on items. `items_fts` gains a `note` column, which
+/// FTS5 can't add in place, so the index and its triggers are recreated and
+/// rebuilt from `items`.
+fn migrate_notes(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "items", "note", "TEXT")?;
+    conn.execute_batch(
+        r#"
+        DROP TRIGGER IF EXISTS items_ai;
+        DROP TRIGGER IF EXISTS items_ad;
+        DROP TRIGGER IF EXISTS items_au;
+        DROP TABLE IF EXISTS items_fts;
+
+        CREATE VIRTUAL TABLE items_fts USING fts5(
+            title,
+            body,
+            note,
+            content='items',
+            content_rowid='id'
+        );
+
+        CREATE TRIGGER items_ai AFTER INSERT ON items BEGIN
+            INSERT INTO items_fts(rowid, title, body, note) VALUES (new.id, new.title, new.body, new.note);
+        END;
+
+        CREATE TRIGGER items_ad AFTER DELETE ON items BEGIN
+            INSERT INTO items_fts(items_fts, rowid, title, body, note)
+                VALUES('delete', old.id, old.title, old.body, old.note);
+        END;
+
+        CREATE TRIGGER items_au AFTER UPDATE ON items BEGIN
+            INSERT INTO items_fts(items_fts, rowid, title, body, note)
+                VALUES('delete', old.id, old.title, old.body, old.note);
+            INSERT INTO items_fts(rowid, title, body, note) VALUES (new.id, new.title, new.body, new.note);
+        END;
+
+        INSERT INTO items_fts(items_fts) VALUES('rebuild');
+        "#,
+    )
+    .context("failed to rebuild search index with notes")?;
+    Ok(())
+}
+
+/// v14: `deleted_at` marks items moved to the trash by `delete` when
+/// `behavior.trash_days` is set. Trashed rows keep their image files until
+/// the retention sweep purges them.
+fn migrate_trash(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "items", "deleted_at", "INTEGER")?;
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS items_deleted_at ON items(deleted_at)")
+        .context("failed to create deleted_at index")?;
+    Ok(())
+}
+
+/// v15: names the `unicode61` tokenizer explicitly, with
+/// `remove_diacritics 2` so accents written as combining characters fold
+/// too. Queries go through the same tokenizer, so `munchen` finds
+/// `München`. The index and its triggers are recreated and rebuilt.
+fn migrate_fts_unicode61(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        DROP TRIGGER IF EXISTS items_ai;
+        DROP TRIGGER IF EXISTS items_ad;
+        DROP TRIGGER IF EXISTS items_au;
+        DROP TABLE IF EXISTS items_fts;
+
+        CREATE VIRTUAL TABLE items_fts USING fts5(
+            title,
+            body,
+            note,
+            content='items',
+            content_rowid='id',
+            tokenize='unicode61 remove_diacritics 2'
+        );
+
+        CREATE TRIGGER items_ai AFTER INSERT ON items BEGIN
+            INSERT INTO items_fts(rowid, title, body, note) VALUES (new.id, new.title, new.body, new.note);
+        END;
+
+        CREATE TRIGGER items_ad AFTER DELETE ON items BEGIN
+            INSERT INTO items_fts(items_fts, rowid, title, body, note)
+                VALUES('delete', old.id, old.title, old.body, old.note);
+        END;
+
+        CREATE TRIGGER items_au AFTER UPDATE ON items BEGIN
+            INSERT INTO items_fts(items_fts, rowid, title, body, note)
+                VALUES('delete', old.id, old.title, old.body, old.note);
+            INSERT INTO items_fts(rowid, title, body, note) VALUES (new.id, new.title, new.body, new.note);
+        END;
+
+        INSERT INTO items_fts(items_fts) VALUES('rebuild');
+        "#,
+    )
+    .context("failed to rebuild search index with the unicode61 tokenizer")?;
+    Ok(())
+}
+
+/// v16: size of each original image in bytes. Existing images get it, and
+/// any dimensions missing since before v8, from the stored blob or file;
+/// images whose data is gone keep NULLs. Listings now read these per item,
+/// so `images` also gets an index on `item_id`.
+fn migrate_image_byte_size(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "images", "byte_size", "INTEGER")?;
+    conn.execute_batch("CREATE INDEX IF NOT EXISTS images_item ON images(item_id)")
+        .context("failed to create images item_id index")?;
+
+    let mut stmt = conn
+        .prepare("SELECT id, bytes, path FROM images WHERE byte_size IS NULL OR width IS NULL OR height IS NULL")
+        .context("failed to query images for backfill")?;
+    let rows = stmt
+        .query_map([], |row| {
+            Ok((row.get::<_, i64>(0)?, row.get::<_, Option<Vec<u8>>>(1)?, row.get::<_, Option<String>>(2)?))
+        })?
+        .collect::<rusqlite::Result<Vec<_>>>()?;
+
+    let mut filled = 0;
+    for (id, bytes, path) in rows {
+        let Ok(data) = crate::clipboard::load_image_bytes(bytes, path.as_deref()) else {
+            continue;
+        };
+        let dimensions = image::io::Reader::new(std::io::Cursor::new(&data))
+            .with_guessed_format()
+            .ok()
+            .and_then(|reader| reader.into_dimensions().ok());
+        conn.execute(
+            "UPDATE images SET byte_size = ?, width = COALESCE(width, ?), height = COALESCE(height, ?) WHERE id = ?",
+            params![data.len() as i64, dimensions.map(|d| d.0), dimensions.map(|d| d.1), id],
+        )
+        .context("failed to backfill image metadata")?;
+        filled += 1;
+    }
+
+    if filled > 0 {
+        info!(images = filled, "backfilled image sizes");
+    }
+    Ok(())
+}
+
+/// v17: further MIME representations captured with an item, beside the
+/// text body or image it is recorded from.
+fn migrate_item_contents(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        r#"
+        CREATE TABLE IF NOT EXISTS item_contents (
+            item_id       INTEGER NOT NULL,
+            mime          TEXT NOT NULL,
+            bytes         BLOB NOT NULL,
+            PRIMARY KEY(item_id, mime),
+            FOREIGN KEY(item_id) REFERENCES items(id) ON DELETE CASCADE
+        );
+        "#,
+    )
+    .context("failed to create item_contents table")?;
+    Ok(())
+}
+
+/// v18: app id of the window an item was copied from, when
+/// `behavior.capture_source` is on.
+fn migrate_source_app(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "items", "source_app", "TEXT")
+}
+
+/// v19: the copied bytes of text items that aren't valid UTF-8, whose
+/// `body` is a lossy decoding; NULL when `body` is already byte-exact. `copy`
+/// restores `raw` when set. Only `body` feeds `items_fts`, so search matches
+/// the decoded text and the triggers don't change.
+fn migrate_raw_text(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "items", "raw", "BLOB")
+}
+
+/// v20: best-guess language of `code` items, e.g. `rust`; NULL when unknown
+/// or for other kinds.
+fn migrate_language(conn: &Connection) -> Result<()> {
+    add_column_if_missing(conn, "items", "language", "TEXT")
+}
+
+fn add_column_if_missing(conn: &Connection, table: &str, column: &str, decl: &str) -> Result<()> {
+    let exists: bool = conn
+        .query_row(
+            &format!("SELECT COUNT(*) FROM pragma_table_info('{table}') WHERE name = ?"),
+            [column],
+            |row| row.get::<_, i64>(0),
+        )
+        .map(|n| n > 0)
+        .with_context(|| format!("failed to inspect table {table}"))?;
+
+    if !exists {
+        conn.execute_batch(&format!("ALTER TABLE {table} ADD COLUMN {column} {decl}"))
+            .with_context(|| format!("failed to add column {table}.{column}"))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A database at schema version `version`, as an older build left it.
+    fn at_version(version: u32) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        for migration in &MIGRATIONS[..version as usize] {
+            migration(&conn).unwrap();
+        }
+        conn.pragma_update(None, "user_version", version).unwrap();
+        conn
+    }
+
+    #[test]
+    fn dropping_unique_hash_keeps_rows_links_and_search() {
+        let conn = at_version(9);
+        conn.execute(
+            "INSERT INTO items (id, created_at, updated_at, title, body, hash) VALUES (7, 1, 1, 'kept', 'needle in body', 'h')",
+            [],
+        )
+        .unwrap();
+        conn.execute("INSERT INTO images (item_id, created_at, mime) VALUES (7, 1, 'image/png')", []).unwrap();
+        assert!(conn
+            .execute("INSERT INTO items (created_at, updated_at, hash) VALUES (2, 2, 'h')", [])
+            .is_err());
+
+        run_migrations(&conn).unwrap();
+        conn.execute("INSERT INTO items (created_at, updated_at, body, hash) VALUES (2, 2, 'again', 'h')", [])
+            .unwrap();
+        let with_hash: i64 = conn.query_row("SELECT COUNT(*) FROM items WHERE hash = 'h'", [], |row| row.get(0)).unwrap();
+        assert_eq!(with_hash, 2);
+        let image_item: i64 = conn.query_row("SELECT item_id FROM images", [], |row| row.get(0)).unwrap();
+        assert_eq!(image_item, 7);
+        let found: i64 = conn
+            .query_row("SELECT rowid FROM items_fts WHERE items_fts MATCH 'needle'", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(found, 7);
+    }
+
+    #[test]
+    fn fresh_databases_reach_the_current_version() {
+        let conn = open_and_init(Path::new(":memory:"), &Storage::default()).unwrap();
+        let version: u32 = conn.pragma_query_value(None, "user_version", |row| row.get(0)).unwrap();
+        assert_eq!(version, SCHEMA_VERSION);
+    }
 }
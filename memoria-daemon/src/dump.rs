@@ -0,0 +1,419 @@
+use anyhow::{Context, Result};
+use base64::Engine;
+use rusqlite::OptionalExtension;
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use tracing::{info, warn};
+
+use crate::clipboard::{self, ClipboardEntry, Selection};
+use crate::config::Config;
+use crate::state::Paths;
+
+/// One item per line of an NDJSON dump.
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DumpRecord {
+    /// Id in the exporting database; ignored on import.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+    pub created_at: i64,
+    pub updated_at: Option<i64>,
+    pub last_used: Option<i64>,
+    #[serde(default)]
+    pub starred: bool,
+    pub title: Option<String>,
+    pub body: Option<String>,
+    pub hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workspace: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_app: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selection: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<DumpImage>,
+    /// Base64-encoded copied bytes of a text item that isn't valid UTF-8.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub raw: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+pub struct DumpImage {
+    pub mime: String,
+    /// Base64-encoded original image bytes.
+    pub data: String,
+}
+
+/// Records committed per transaction during import.
+const IMPORT_BATCH_SIZE: u64 = 500;
+
+#[derive(Debug, Default)]
+pub struct ImportResult {
+    pub imported: u64,
+    /// Records whose hash already exists.
+    pub skipped: u64,
+    /// Malformed lines and records that failed to insert.
+    pub errors: u64,
+}
+
+/// Writes every item outside the trash as one JSON object per line to `path`
+/// (created `0600`). With `include_images`, original image bytes are embedded
+/// as base64.
+pub fn export_items(conn: &rusqlite::Connection, path: &Path, include_images: bool) -> Result<u64> {
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("failed to create export file: {}", path.display()))?;
+    let mut writer = std::io::BufWriter::new(file);
+
+    let mut stmt = conn
+        .prepare(
+            "SELECT id, created_at, updated_at, last_used, starred, title, body, hash, kind, workspace, selection, note, source_app, raw, language \
+             FROM items WHERE deleted_at IS NULL ORDER BY id",
+        )
+        .context("failed to prepare export query")?;
+    let mut image_stmt = conn
+        .prepare("SELECT mime, bytes, path FROM images WHERE item_id = ? LIMIT 1")
+        .context("failed to prepare image export query")?;
+    let mut tag_stmt = conn
+        .prepare("SELECT tag FROM tags WHERE item_id = ? ORDER BY tag")
+        .context("failed to prepare tag export query")?;
+
+    let mut rows = stmt.query([]).context("failed to query items for export")?;
+    let mut exported: u64 = 0;
+
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+
+        let image = if include_images {
+            image_stmt
+                .query_row([id], |r| {
+                    Ok((r.get::<_, String>(0)?, r.get::<_, Option<Vec<u8>>>(1)?, r.get::<_, Option<String>>(2)?))
+                })
+                .optional()?
+                .and_then(|(mime, bytes, path)| {
+                    match clipboard::load_image_bytes(bytes, path.as_deref()) {
+                        Ok(bytes) => Some(DumpImage {
+                            mime,
+                            data: base64::engine::general_purpose::STANDARD.encode(bytes),
+                        }),
+                        Err(err) => {
+                            warn!(id, error=%err, "exporting image item without its image");
+                            None
+                        }
+                    }
+                })
+        } else {
+            None
+        };
+
+        let record = DumpRecord {
+            id: Some(id),
+            created_at: row.get(1)?,
+            updated_at: row.get(2)?,
+            last_used: row.get(3)?,
+            starred: row.get::<_, i64>(4)? != 0,
+            title: row.get(5)?,
+            body: row.get(6)?,
+            hash: row.get(7)?,
+            kind: row.get(8)?,
+            workspace: row.get(9)?,
+            selection: row.get(10)?,
+            note: row.get(11)?,
+            source_app: row.get(12)?,
+            language: row.get(14)?,
+            raw: row
+                .get::<_, Option<Vec<u8>>>(13)?
+                .map(|raw| base64::engine::general_purpose::STANDARD.encode(raw)),
+            tags: tag_stmt
+                .query_map([id], |r| r.get(0))?
+                .collect::<rusqlite::Result<Vec<String>>>()?,
+            image,
+        };
+
+        serde_json::to_writer(&mut writer, &record).context("failed to serialize item")?;
+        writer.write_all(b"\n").context("failed to write export file")?;
+        exported += 1;
+    }
+
+    writer.flush().context("failed to flush export file")?;
+    info!(path=%path.display(), exported, include_images, "export completed");
+
+    Ok(exported)
+}
+
+pub fn import_items(conn: &rusqlite::Connection, path: &Path, cfg: &Config, paths: &Paths) -> Result<ImportResult> {
+    let file = std::fs::File::open(path)
+        .with_context(|| format!("failed to open dump: {}", path.display()))?;
+    let reader = std::io::BufReader::new(file);
+
+    let mut result = ImportResult::default();
+    let mut tx = conn.unchecked_transaction()?;
+    let mut in_batch: u64 = 0;
+
+    for (idx, line) in reader.lines().enumerate() {
+        let line_no = idx + 1;
+        let line = line.with_context(|| format!("failed to read dump line {line_no}"))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: DumpRecord = match serde_json::from_str(&line) {
+            Ok(record) => record,
+            Err(err) => {
+                warn!(line = line_no, error=%err, "skipping malformed dump line");
+                result.errors += 1;
+                continue;
+            }
+        };
+
+        // A record that fails halfway is rolled back on its own, keeping
+        // the rest of the batch.
+        let savepoint = tx.savepoint().context("failed to start import savepoint")?;
+        match import_record(&savepoint, record, cfg, paths).and_then(|imported| {
+            savepoint.commit().context("failed to release import savepoint")?;
+            Ok(imported)
+        }) {
+            Ok(true) => result.imported += 1,
+            Ok(false) => result.skipped += 1,
+            Err(err) => {
+                warn!(line = line_no, error=%err, "skipping dump line that failed to import");
+                result.errors += 1;
+            }
+        }
+
+        in_batch += 1;
+        if in_batch >= IMPORT_BATCH_SIZE {
+            tx.commit().context("failed to commit import batch")?;
+            tx = conn.unchecked_transaction()?;
+            in_batch = 0;
+        }
+    }
+
+    tx.commit().context("failed to commit import batch")?;
+
+    info!(
+        path=%path.display(),
+        imported = result.imported,
+        skipped = result.skipped,
+        errors = result.errors,
+        "import completed"
+    );
+
+    Ok(result)
+}
+
+/// Returns `Ok(false)` when an item with the same hash already exists.
+fn import_record(conn: &rusqlite::Connection, record: DumpRecord, cfg: &Config, paths: &Paths) -> Result<bool> {
+    let image_entry = match &record.image {
+        Some(image) => {
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(&image.data)
+                .context("invalid base64 image data")?;
+            // The stored bytes may be a downscaled or re-encoded copy; the
+            // item keeps the hash of what was copied, so dedupe on that.
+            let mut entry = ClipboardEntry::new(image.mime.clone(), bytes);
+            if let Some(hash) = &record.hash {
+                entry.hash = hash.clone();
+            }
+            Some(entry)
+        }
+        None => None,
+    };
+
+    let raw = match &record.raw {
+        Some(raw) => Some(
+            base64::engine::general_purpose::STANDARD
+                .decode(raw)
+                .context("invalid base64 raw text")?,
+        ),
+        None => None,
+    };
+    let body = record.body.unwrap_or_default();
+    let hash = match &image_entry {
+        Some(entry) => entry.hash.clone(),
+        None => record
+            .hash
+            .unwrap_or_else(|| clipboard::text_hash(raw.as_deref().unwrap_or(body.as_bytes()), cfg.behavior.normalize_text)),
+    };
+
+    let exists: Option<i64> = conn
+        .query_row("SELECT id FROM items WHERE hash = ?", [&hash], |row| row.get(0))
+        .optional()
+        .context("failed to query items by hash")?;
+    if exists.is_some() {
+        return Ok(false);
+    }
+
+    let files = match &image_entry {
+        Some(entry) => Some(clipboard::write_image_files(entry, &cfg.grid, paths)?),
+        None => None,
+    };
+
+    let updated_at = record.updated_at.unwrap_or(record.created_at);
+    let last_used = record.last_used.unwrap_or(updated_at);
+    let kind = match (&image_entry, record.kind) {
+        (Some(_), _) => "image".to_string(),
+        (None, Some(kind)) => kind,
+        (None, None) => clipboard::classify_text(&body).to_string(),
+    };
+    let language = match record.language {
+        Some(language) => Some(language),
+        None if kind == "code" => crate::code::language(&body).map(str::to_string),
+        None => None,
+    };
+
+    let selection = record
+        .selection
+        .as_deref()
+        .and_then(Selection::parse)
+        .unwrap_or(Selection::Clipboard);
+
+    let inserted = (|| -> Result<()> {
+        conn.execute(
+            "INSERT INTO items (created_at, updated_at, last_used, starred, title, body, hash, kind, workspace, selection, note, source_app, raw, language) \
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
+            rusqlite::params![
+                record.created_at,
+                updated_at,
+                last_used,
+                record.starred as i64,
+                record.title,
+                body,
+                hash,
+                kind,
+                record.workspace,
+                selection.as_str(),
+                record.note,
+                record.source_app,
+                raw,
+                language
+            ],
+        )
+        .context("failed to insert imported item")?;
+
+        let item_id = conn.last_insert_rowid();
+        for tag in &record.tags {
+            let tag = tag.trim().to_lowercase();
+            if !tag.is_empty() {
+                conn.execute("INSERT OR IGNORE INTO tags (item_id, tag) VALUES (?, ?)", rusqlite::params![item_id, tag])
+                    .context("failed to insert imported tag")?;
+            }
+        }
+
+        if let (Some(entry), Some(files)) = (&image_entry, &files) {
+            conn.execute(
+                "INSERT INTO images (item_id, created_at, mime, bytes, phash, width, height, byte_size, path) \
+                 VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
+                rusqlite::params![
+                    item_id,
+                    record.created_at,
+                    entry.mime,
+                    cfg.behavior.store_image_blobs.then_some(entry.data.as_slice()),
+                    entry.phash.map(|p| p as i64),
+                    files.width,
+                    files.height,
+                    entry.data.len() as i64,
+                    files.original.to_string_lossy()
+                ],
+            )
+            .context("failed to insert imported image")?;
+        }
+        Ok(())
+    })();
+    if let Err(err) = inserted {
+        // Nothing else has this hash (checked above), so the files written
+        // for it go with the rolled-back record.
+        if files.is_some() {
+            if let Err(err) = crate::retention::delete_image_files(&paths.data_dir, &hash) {
+                warn!(hash=%hash, error=%err, "failed to remove image files of a failed import");
+            }
+        }
+        return Err(err);
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::AppState;
+
+    fn record(body: &str) -> DumpRecord {
+        DumpRecord {
+            id: None,
+            created_at: 1,
+            updated_at: None,
+            last_used: None,
+            starred: false,
+            title: Some(body.to_string()),
+            body: Some(body.to_string()),
+            hash: None,
+            kind: None,
+            language: None,
+            workspace: None,
+            source_app: None,
+            selection: None,
+            tags: Vec::new(),
+            note: None,
+            image: None,
+            raw: None,
+        }
+    }
+
+    fn png() -> Vec<u8> {
+        let mut out = std::io::Cursor::new(Vec::new());
+        image::RgbImage::from_pixel(4, 4, image::Rgb([200, 10, 10]))
+            .write_to(&mut out, image::ImageFormat::Png)
+            .unwrap();
+        out.into_inner()
+    }
+
+    #[test]
+    fn a_record_failing_halfway_leaves_nothing_behind() {
+        let state = AppState::for_tests();
+        let mut picture = record("");
+        picture.image = Some(DumpImage {
+            mime: "image/png".to_string(),
+            data: base64::engine::general_purpose::STANDARD.encode(png()),
+        });
+        let dump = state.paths.data_dir.join("dump.ndjson");
+        let lines: Vec<String> = [record("before"), picture, record("after")]
+            .iter()
+            .map(|record| serde_json::to_string(record).unwrap())
+            .collect();
+        std::fs::write(&dump, lines.join("\n")).unwrap();
+
+        let conn = state.conn.lock().unwrap();
+        conn.execute_batch(
+            "CREATE TRIGGER fail_images BEFORE INSERT ON images BEGIN SELECT RAISE(ABORT, 'disk full'); END;",
+        )
+        .unwrap();
+        let result = import_items(&conn, &dump, &Config::default(), &state.paths).unwrap();
+        assert_eq!((result.imported, result.skipped, result.errors), (2, 0, 1));
+
+        let bodies: Vec<String> = conn
+            .prepare("SELECT body FROM items ORDER BY id")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        assert_eq!(bodies, ["before", "after"]);
+        let originals = std::fs::read_dir(state.paths.originals_dir()).map_or(0, |dir| dir.count());
+        assert_eq!(originals, 0);
+    }
+}
@@ -0,0 +1,55 @@
+use std::fmt;
+
+/// No item has the requested id.
+#[derive(Debug)]
+pub struct ItemNotFound(pub i64);
+
+impl fmt::Display for ItemNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "item with id {} not found", self.0)
+    }
+}
+
+impl std::error::Error for ItemNotFound {}
+
+/// A well-formed request whose arguments can't be acted on, such as a
+/// timestamp in the future.
+#[derive(Debug)]
+pub struct InvalidArgument(pub String);
+
+impl fmt::Display for InvalidArgument {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for InvalidArgument {}
+
+/// None of the paste tools tried is installed.
+#[derive(Debug)]
+pub struct PasteToolUnavailable(pub Vec<String>);
+
+impl fmt::Display for PasteToolUnavailable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "no paste tool found (tried: {})", self.0.join(", "))
+    }
+}
+
+impl std::error::Error for PasteToolUnavailable {}
+
+/// A thread panicked while holding a shared lock.
+#[derive(Debug)]
+pub struct LockPoisoned(String);
+
+impl fmt::Display for LockPoisoned {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "lock poisoned: {}", self.0)
+    }
+}
+
+impl std::error::Error for LockPoisoned {}
+
+/// For `.map_err` on `Mutex::lock` and friends.
+pub fn lock_poisoned<T>(err: std::sync::PoisonError<T>) -> anyhow::Error {
+    LockPoisoned(err.to_string()).into()
+}
@@ -0,0 +1,113 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value;
+use tracing::debug;
+
+use crate::config::{AppLookup, Behavior};
+
+/// App id of the focused window, looked up as `behavior.app_lookup` says.
+/// Best-effort: `None` when lookup is off or the compositor can't be asked.
+pub async fn focused_app(behavior: &Behavior) -> Option<String> {
+    let lookup = match behavior.app_lookup {
+        AppLookup::None => return None,
+        AppLookup::Hyprland => hyprland().await,
+        AppLookup::Sway => sway().await,
+        AppLookup::Command => match &behavior.app_command {
+            Some(command) => command_output(command).await,
+            None => Err(anyhow!("app_lookup is \"command\" but app_command is unset")),
+        },
+    };
+    match lookup {
+        Ok(app) => app.filter(|app| !app.is_empty()),
+        Err(err) => {
+            debug!(error=%format!("{err:#}"), "failed to look up the focused app");
+            None
+        }
+    }
+}
+
+/// Whether `app` matches one of `patterns`: exact, or a glob where `*` is
+/// any run of characters and `?` any one. Case is ignored, since app ids
+/// and X11 classes disagree on it.
+pub fn app_matches(patterns: &[String], app: &str) -> bool {
+    patterns.iter().any(|pattern| crate::transform::glob_match(pattern, app))
+}
+
+async fn run(program: &str, args: &[&str]) -> Result<Vec<u8>> {
+    let output = tokio::process::Command::new(program)
+        .args(args)
+        .output()
+        .await
+        .with_context(|| format!("failed to run {program}"))?;
+    if !output.status.success() {
+        return Err(anyhow!("{program} exited with {}", output.status));
+    }
+    Ok(output.stdout)
+}
+
+async fn hyprland() -> Result<Option<String>> {
+    let window: Value = serde_json::from_slice(&run("hyprctl", &["activewindow", "-j"]).await?)
+        .context("invalid hyprctl output")?;
+    Ok(window.get("class").and_then(Value::as_str).map(str::to_string))
+}
+
+async fn sway() -> Result<Option<String>> {
+    let tree: Value = serde_json::from_slice(&run("swaymsg", &["-t", "get_tree"]).await?)
+        .context("invalid swaymsg output")?;
+    Ok(focused_node(&tree).and_then(|node| {
+        node.get("app_id")
+            .and_then(Value::as_str)
+            // XWayland windows have no app id, only an X11 class.
+            .or_else(|| node.pointer("/window_properties/class").and_then(Value::as_str))
+            .map(str::to_string)
+    }))
+}
+
+fn focused_node(node: &Value) -> Option<&Value> {
+    if node.get("focused").and_then(Value::as_bool) == Some(true) {
+        return Some(node);
+    }
+    ["nodes", "floating_nodes"]
+        .iter()
+        .filter_map(|key| node.get(key).and_then(Value::as_array))
+        .flatten()
+        .find_map(focused_node)
+}
+
+async fn command_output(command: &str) -> Result<Option<String>> {
+    let stdout = run("sh", &["-c", command]).await?;
+    Ok(Some(String::from_utf8_lossy(&stdout).trim().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> Vec<String> {
+        patterns.iter().map(|pattern| pattern.to_string()).collect()
+    }
+
+    #[test]
+    fn app_matches_exact_ids() {
+        let excluded = patterns(&["org.keepassxc.KeePassXC", "Bitwarden"]);
+        assert!(app_matches(&excluded, "org.keepassxc.KeePassXC"));
+        assert!(app_matches(&excluded, "bitwarden"));
+        assert!(!app_matches(&excluded, "org.keepassxc"));
+        assert!(!app_matches(&excluded, "firefox"));
+    }
+
+    #[test]
+    fn app_matches_globs() {
+        let excluded = patterns(&["*pass*", "kitty-?"]);
+        assert!(app_matches(&excluded, "1Password"));
+        assert!(app_matches(&excluded, "org.keepassxc.KeePassXC"));
+        assert!(app_matches(&excluded, "kitty-2"));
+        assert!(!app_matches(&excluded, "kitty"));
+        assert!(!app_matches(&excluded, "kitty-10"));
+    }
+
+    #[test]
+    fn nothing_matches_an_empty_list() {
+        assert!(!app_matches(&[], "firefox"));
+        assert!(!app_matches(&[], ""));
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashSet;
+
+/// Minimum score for a candidate to count as a fuzzy match.
+pub const MATCH_THRESHOLD: f32 = 0.7;
+
+/// Only this many leading characters of a body are considered, so huge
+/// pastes don't dominate the scan.
+const MAX_SCANNED_CHARS: usize = 4096;
+
+/// Splits text into lowercase alphanumeric words.
+pub fn tokenize(text: &str) -> Vec<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+/// Scores how well `query_tokens` match `text`, in `0.0..=1.0`.
+///
+/// Each query token is matched against its most similar word in the text
+/// (1 - normalized edit distance, with prefixes counting as exact), and the
+/// per-token scores are averaged.
+pub fn score(query_tokens: &[String], text: &str) -> f32 {
+    if query_tokens.is_empty() {
+        return 0.0;
+    }
+
+    let scanned: String = text.chars().take(MAX_SCANNED_CHARS).collect();
+    let words = tokenize(&scanned);
+    if words.is_empty() {
+        return 0.0;
+    }
+
+    let total: f32 = query_tokens
+        .iter()
+        .map(|q| {
+            words
+                .iter()
+                .map(|w| word_similarity(q, w))
+                .fold(0.0, f32::max)
+        })
+        .sum();
+
+    total / query_tokens.len() as f32
+}
+
+/// Jaccard similarity of the word sets of `a` and `b`, in `0.0..=1.0`.
+/// Whitespace, punctuation and case differences don't count.
+pub fn token_set_similarity(a: &HashSet<String>, b: &HashSet<String>) -> f32 {
+    if a.is_empty() && b.is_empty() {
+        return 1.0;
+    }
+    let shared = a.intersection(b).count();
+    shared as f32 / (a.len() + b.len() - shared) as f32
+}
+
+fn word_similarity(query: &str, word: &str) -> f32 {
+    if word.starts_with(query) {
+        return 1.0;
+    }
+
+    let q_len = query.chars().count();
+    let w_len = word.chars().count();
+    let longest = q_len.max(w_len);
+    if longest == 0 || q_len.abs_diff(w_len) * 2 > longest {
+        return 0.0;
+    }
+
+    1.0 - levenshtein(query, word) as f32 / longest as f32
+}
+
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(cur[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+
+    prev[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(text: &str) -> HashSet<String> {
+        tokenize(text).into_iter().collect()
+    }
+
+    #[test]
+    fn tokenizes_into_lowercase_words() {
+        assert_eq!(tokenize("Hello, World! foo_bar 42"), ["hello", "world", "foo", "bar", "42"]);
+        assert_eq!(tokenize("Größe über"), ["größe", "über"]);
+        assert!(tokenize(" -- ").is_empty());
+    }
+
+    #[test]
+    fn levenshtein_counts_edits() {
+        assert_eq!(levenshtein("", ""), 0);
+        assert_eq!(levenshtein("abc", ""), 3);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+        assert_eq!(levenshtein("kubernets", "kubernetes"), 1);
+        assert_eq!(levenshtein("straße", "strasse"), 2);
+    }
+
+    #[test]
+    fn typos_score_above_the_threshold() {
+        let query = tokenize("kubernets");
+        assert!(score(&query, "notes on kubernetes networking") >= MATCH_THRESHOLD);
+        let query = tokenize("recieve");
+        assert!(score(&query, "Receive the package") >= MATCH_THRESHOLD);
+    }
+
+    #[test]
+    fn prefixes_score_as_exact() {
+        assert_eq!(score(&tokenize("kube"), "kubernetes"), 1.0);
+    }
+
+    #[test]
+    fn unrelated_text_scores_below_the_threshold() {
+        assert!(score(&tokenize("kubernetes"), "grocery list: milk, eggs") < MATCH_THRESHOLD);
+        // Words of very different lengths aren't compared at all.
+        assert_eq!(score(&tokenize("a"), "abracadabra"), 1.0);
+        assert_eq!(score(&tokenize("xyz"), "abracadabra"), 0.0);
+    }
+
+    #[test]
+    fn scores_average_over_query_tokens() {
+        let both = score(&tokenize("docker kubernetes"), "docker and kubernetes");
+        let one = score(&tokenize("docker qwertyuiop"), "docker and kubernetes");
+        assert_eq!(both, 1.0);
+        assert!(one < both && one >= 0.5);
+    }
+
+    #[test]
+    fn empty_inputs_score_zero() {
+        assert_eq!(score(&[], "anything"), 0.0);
+        assert_eq!(score(&tokenize("query"), ""), 0.0);
+        assert_eq!(score(&tokenize("query"), "!!!"), 0.0);
+    }
+
+    #[test]
+    fn token_set_similarity_is_jaccard() {
+        assert_eq!(token_set_similarity(&words("a b c"), &words("C, b; A!")), 1.0);
+        assert_eq!(token_set_similarity(&words("a b"), &words("b c")), 1.0 / 3.0);
+        assert_eq!(token_set_similarity(&words("a b"), &words("c d")), 0.0);
+        assert_eq!(token_set_similarity(&words(""), &words("")), 1.0);
+        assert_eq!(token_set_similarity(&words("a"), &words("")), 0.0);
+    }
+}
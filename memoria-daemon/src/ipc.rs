@@ -1,92 +1,254 @@
 use anyhow::{anyhow, Context, Result};
+use rusqlite::types::Value as SqlValue;
 use rusqlite::OptionalExtension;
 use serde::Serialize;
 use serde_json::Value;
+use std::path::Path;
 use std::sync::{Arc, Mutex};
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::net::UnixStream;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::process::Command;
-use tracing::{error};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error, info, warn};
 
+use crate::clipboard::Selection;
+use crate::config::Config;
+use crate::error::{lock_poisoned, InvalidArgument, ItemNotFound, LockPoisoned, PasteToolUnavailable};
+use crate::state::{AppState, Paths};
+
+pub use memoria_client::{IpcError, ItemSummary};
+
+/// Highest protocol version this daemon speaks.
+pub const PROTOCOL_VERSION: u32 = 1;
 
 #[derive(Debug)]
 pub enum IpcRequest {
-    List { limit: Option<u32>, starred_only: bool },
-    Search { query: String, limit: Option<u32> },
-    Gallery { limit: Option<u32> },
-    Star { id: i64, value: bool },
-    Copy { id: i64 },
+    List { limit: Option<u32>, filter: ItemFilter, sort: Option<ListSort>, format: ItemFormat },
+    /// How many items `List` with the same filter would return without a limit.
+    Count { filter: ItemFilter },
+    /// `snippet_open`/`snippet_close` override the configured highlight markers.
+    Search {
+        query: String,
+        limit: Option<u32>,
+        filter: ItemFilter,
+        fuzzy: FuzzyMode,
+        rank_mode: RankMode,
+        format: ItemFormat,
+        snippet_open: Option<String>,
+        snippet_close: Option<String>,
+    },
+    /// `search` with `regex` instead of `query`: items whose title, body or
+    /// note match, newest first.
+    RegexSearch { regex: regex::Regex, limit: Option<u32>, filter: ItemFilter, format: ItemFormat },
+    Gallery { limit: Option<u32>, filter: GalleryFilter, format: ItemFormat },
+    /// One item with its full body.
+    Get { id: i64, format: ItemFormat },
+    /// Accepts a single `id` or an `ids` array.
+    Star { ids: Vec<i64>, value: bool },
+    /// `target` overrides the selection the item was captured from.
+    Copy { id: i64, target: Option<Selection>, mime: CopyMime },
+    /// Like `Copy`, but HTML bodies are reduced to plain text first.
+    CopyPlain { id: i64, target: Option<Selection> },
+    AddTag { id: i64, tag: String },
+    /// Sets or, with `None`, clears an item's note.
+    SetNote { id: i64, note: Option<String> },
+    /// Replaces the item's title; at most `behavior.title_max_chars` characters.
+    Rename { id: i64, title: String },
+    /// Corrects the kind of a text item; `language` only applies to `code`.
+    SetKind { id: i64, kind: String, language: Option<String> },
+    RemoveTag { id: i64, tag: String },
+    /// Text items whose word sets overlap item `id`'s by at least `threshold`.
+    FindSimilar { id: i64, threshold: f32, limit: u32 },
+    /// Marks an item as used without copying it, for clients that paste by
+    /// other means. Also accepted as `mark_used`.
+    Touch { id: i64 },
+    /// Timestamps an item was used at, newest first.
+    History { id: i64, limit: Option<u32> },
+    /// Copies an item, then simulates a paste into the focused window.
+    Paste { id: i64 },
 
-    Delete { ids: Vec<i64> },
+    /// Deletes items; starred ones are skipped unless `force` is set.
+    /// `delete_items` is a deprecated alias that always forces. With
+    /// `behavior.trash_days` set, items go to the trash unless `permanent`.
+    Delete { ids: Vec<i64>, force: bool, permanent: bool },
+    /// Takes items back out of the trash.
+    Restore { ids: Vec<i64> },
+    /// Trashed items, most recently deleted first.
+    Trash { limit: Option<u32>, format: ItemFormat },
+    /// Removes an item's image and its files but keeps the item.
+    DeleteImage { id: i64 },
     DeleteAllExceptStarred,
-    DeleteItems { ids: Vec<i64> },
+    /// Deletes items matching the filters; starred items only with `include_starred`.
+    Clear { older_than_days: Option<u32>, kind: Option<String>, include_starred: bool },
+    DeleteOlderThan { timestamp: i64, include_starred: bool },
+    /// Reclaims database space and removes orphaned image files.
+    Vacuum,
+    /// Rebuilds the full-text index from `items`.
+    RebuildIndex,
+    /// Item counts and storage usage.
+    Stats,
     GetSettings,
+    Import { path: String },
+    Export { path: String, include_images: bool },
+    Reorder { id: i64, position: u32 },
+    SetPosition { id: i64, position: Option<u32> },
+    /// Protocol negotiation plus capability discovery. Also accepted as
+    /// `handshake`, with `client_version` for `protocol`.
+    Hello { client: Option<String>, protocol: Option<u32> },
+    SetSettings { patch: serde_json::Map<String, Value> },
+    Ping,
+    /// Stops recording clipboard changes until `Resume`.
+    Pause,
+    Resume,
+    /// Whether capture is currently paused.
+    Status,
+    /// Pauses capture for `minutes`, replacing any running incognito window.
+    Incognito { minutes: u32 },
 }
 
 #[derive(Debug, Serialize)]
 pub struct IpcResponse<T> {
     pub ok: bool,
+    /// Echo of the request's `request_id`, for correlating pipelined requests.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<u32>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<T>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
+    /// Machine-readable category of `error`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<IpcError>,
+}
+
+/// Categorizes `err` by the typed errors in its chain, defaulting to `Internal`.
+pub fn error_code(err: &anyhow::Error) -> IpcError {
+    for cause in err.chain() {
+        if cause.is::<ItemNotFound>() {
+            return IpcError::NotFound;
+        }
+        if cause.is::<LockPoisoned>() {
+            return IpcError::LockPoisoned;
+        }
+        if cause.is::<InvalidArgument>() {
+            return IpcError::InvalidRequest;
+        }
+        if cause.is::<PasteToolUnavailable>() {
+            return IpcError::PasteToolUnavailable;
+        }
+    }
+    IpcError::Internal
 }
 
 impl<T> IpcResponse<T> {
     pub fn ok(data: T) -> Self {
         Self {
             ok: true,
+            request_id: None,
+            version: None,
             data: Some(data),
             error: None,
+            code: None,
         }
     }
 
     pub fn err(msg: impl Into<String>) -> Self {
         Self {
             ok: false,
+            request_id: None,
+            version: None,
             data: None,
             error: Some(msg.into()),
+            code: Some(IpcError::Internal),
         }
     }
+
+    pub fn with_code(mut self, code: IpcError) -> Self {
+        self.code = Some(code);
+        self
+    }
+
+    pub fn with_request_id(mut self, request_id: Option<Value>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    pub fn with_version(mut self, version: u32) -> Self {
+        self.version = Some(version);
+        self
+    }
 }
 
-#[derive(Debug, Serialize)]
-pub struct ItemSummary {
-    pub id: i64,
-    pub title: Option<String>,
-    pub body: Option<String>,
-    pub created_at: i64,
-    pub updated_at: i64,
-    pub last_used: Option<i64>,
-    pub starred: bool,
-    pub hash: Option<String>,
-    pub has_image: bool,
-    #[serde(skip_serializing_if = "Option::is_none")]
-    pub thumbnail_path: Option<String>,
+/// Per-connection state negotiated with the client.
+struct Session {
+    protocol_version: u32,
 }
 
-pub async fn handle_connection(stream: UnixStream, conn: Arc<Mutex<rusqlite::Connection>>, cfg: Arc<crate::config::Config>) {
-    let (reader, mut writer) = stream.into_split();
+/// Serves one client connection. With `token` set, the first line must be
+/// that token or the connection is closed. Once `shutdown` is cancelled the
+/// connection closes instead of reading another request; one already being
+/// handled still gets its response.
+pub async fn handle_connection<S>(stream: S, state: Arc<AppState>, token: Option<String>, shutdown: CancellationToken)
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let (reader, mut writer) = tokio::io::split(stream);
     let mut lines = BufReader::new(reader).lines();
+    let mut session = Session { protocol_version: PROTOCOL_VERSION };
+
+    if let Some(token) = token {
+        let authorized = matches!(
+            next_line(&mut lines, &shutdown).await,
+            Ok(Some(line)) if token_matches(line.trim(), &token)
+        );
+        if !authorized {
+            warn!("rejected connection with missing or invalid token");
+            let resp = IpcResponse::<()>::err("unauthorized")
+                .with_code(IpcError::Unauthorized)
+                .with_version(session.protocol_version);
+            let _ = writer.write_all(format_json(&resp).as_bytes()).await;
+            return;
+        }
+    }
 
-    while let Ok(Some(line)) = lines.next_line().await {
+    while let Ok(Some(line)) = next_line(&mut lines, &shutdown).await {
         if line.trim().is_empty() {
             continue;
         }
 
-        let parsed: IpcRequest = match parse_request(&line) {
+        let value: Value = match serde_json::from_str(&line) {
+            Ok(value) => value,
+            Err(err) => {
+                let resp = IpcResponse::<()>::err(format!("invalid json: {err}"))
+                    .with_code(IpcError::InvalidRequest)
+                    .with_version(session.protocol_version);
+                let _ = writer.write_all(format_json(&resp).as_bytes()).await;
+                continue;
+            }
+        };
+
+        let request_id = request_id(&value);
+
+        let parsed: IpcRequest = match parse_request(&value) {
             Ok(req) => req,
             Err(err) => {
-                let _ = writer
-                    .write_all(format_json(&IpcResponse::<()>::err(format!("invalid json: {err}"))).as_bytes())
-                    .await;
+                let resp = IpcResponse::<()>::err(format!("invalid request: {err}"))
+                    .with_code(IpcError::InvalidRequest)
+                    .with_request_id(request_id)
+                    .with_version(session.protocol_version);
+                let _ = writer.write_all(format_json(&resp).as_bytes()).await;
                 continue;
             }
         };
 
-        let response = dispatch_request(&conn, &cfg, parsed)
+        let response = dispatch_request(&state, &mut session, parsed)
             .await
-            .unwrap_or_else(|err| IpcResponse::<serde_json::Value>::err(format!("{err}")));
+            .unwrap_or_else(|err| {
+                IpcResponse::<serde_json::Value>::err(format!("{err}")).with_code(error_code(&err))
+            })
+            .with_request_id(request_id)
+            .with_version(session.protocol_version);
 
         if let Err(err) = writer.write_all(format_json(&response).as_bytes()).await {
             error!(error=%err, "failed to write IPC response");
@@ -95,13 +257,93 @@ pub async fn handle_connection(stream: UnixStream, conn: Arc<Mutex<rusqlite::Con
     }
 }
 
+/// The request's `request_id`, kept apart from the `id` argument many
+/// commands take. Only strings and numbers are echoed.
+fn request_id(value: &Value) -> Option<Value> {
+    value
+        .get("request_id")
+        .filter(|id| id.is_string() || id.is_number())
+        .cloned()
+}
+
+/// The next line from the client, or `None` once it hangs up or `shutdown`
+/// is cancelled.
+async fn next_line<R>(lines: &mut tokio::io::Lines<R>, shutdown: &CancellationToken) -> std::io::Result<Option<String>>
+where
+    R: AsyncBufRead + Unpin,
+{
+    tokio::select! {
+        biased;
+        _ = shutdown.cancelled() => Ok(None),
+        line = lines.next_line() => line,
+    }
+}
+
+/// Compares without bailing out at the first differing byte.
+fn token_matches(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
 fn format_json<T: Serialize>(resp: &IpcResponse<T>) -> String {
     serde_json::to_string(resp).unwrap_or_else(|e| {
         format!("{{\"ok\":false,\"error\":\"serialization error: {e}\"}}")
     }) + "\n"
 }
-fn parse_request(line: &str) -> Result<IpcRequest> {
-    let v: Value = serde_json::from_str(line)?;
+
+/// Every `cmd` accepted by `parse_request`, advertised by `hello` and its
+/// alias `handshake`. `parse_request` rejects anything not listed here, so a command
+/// can't be served without being advertised.
+pub const COMMANDS: &[&str] = &[
+    "hello",
+    "list",
+    "count",
+    "search",
+    "gallery",
+    "get",
+    "star",
+    "copy",
+    "copy_plain",
+    "paste",
+    "touch",
+    "mark_used",
+    "history",
+    "find_similar",
+    "add_tag",
+    "remove_tag",
+    "set_note",
+    "rename",
+    "set_kind",
+    "delete",
+    "delete_image",
+    "restore",
+    "trash",
+    "delete_all_except_starred",
+    "clear",
+    "delete_older_than",
+    "vacuum",
+    "rebuild_index",
+    "stats",
+    "delete_items",
+    "get_settings",
+    "import",
+    "export",
+    "reorder",
+    "set_position",
+    "handshake",
+    "set_settings",
+    "ping",
+    "pause",
+    "resume",
+    "status",
+    "incognito",
+];
+
+fn parse_request(v: &Value) -> Result<IpcRequest> {
     let obj = v
         .as_object()
         .ok_or_else(|| anyhow!("request must be a JSON object"))?;
@@ -113,6 +355,9 @@ fn parse_request(line: &str) -> Result<IpcRequest> {
         .as_str()
         .ok_or_else(|| anyhow!("cmd must be a string"))?
         .to_lowercase();
+    if !COMMANDS.contains(&cmd.as_str()) {
+        return Err(anyhow!("unknown cmd: {cmd}"));
+    }
 
     let args_obj = obj
         .get("args")
@@ -127,516 +372,2570 @@ fn parse_request(line: &str) -> Result<IpcRequest> {
     match cmd.as_str() {
         "list" => {
             let limit = get("limit").and_then(|v| v.as_u64()).map(|n| n as u32);
-            let starred_only = get("starred_only").and_then(|v| v.as_bool()).unwrap_or(false);
-            Ok(IpcRequest::List { limit, starred_only })
+            let sort = parse_sort(get("sort"), get("order"))?;
+            Ok(IpcRequest::List {
+                limit,
+                filter: ItemFilter::parse(get)?,
+                sort,
+                format: ItemFormat::parse(get)?,
+            })
         }
+        "count" => Ok(IpcRequest::Count { filter: ItemFilter::parse(get)? }),
         "search" => {
+            let limit = get("limit").and_then(|v| v.as_u64()).map(|n| n as u32);
+            let workspace = get("workspace").and_then(|v| v.as_str()).map(|s| s.to_string());
+            if let Some(pattern) = get("regex") {
+                if get("query").is_some() {
+                    return Err(anyhow!("search takes query or regex, not both"));
+                }
+                let pattern = pattern.as_str().ok_or_else(|| anyhow!("regex must be a string"))?;
+                let case_insensitive = get("case_insensitive").and_then(|v| v.as_bool()).unwrap_or(false);
+                let regex = regex::RegexBuilder::new(pattern)
+                    .case_insensitive(case_insensitive)
+                    .size_limit(REGEX_SIZE_LIMIT)
+                    .build()
+                    .map_err(|e| anyhow!("invalid regex: {e}"))?;
+                let starred_only = get("starred_only").and_then(|v| v.as_bool()).unwrap_or(false);
+                let (since, until) = parse_time_range(get("since"), get("until"))?;
+                let selection = parse_selection("selection", get("selection"))?;
+                return Ok(IpcRequest::RegexSearch {
+                    regex,
+                    limit,
+                    filter: ItemFilter { starred_only, workspace, since, until, selection, ..Default::default() },
+                    format: ItemFormat::parse(get)?,
+                });
+            }
             let query = get("query")
                 .and_then(|v| v.as_str())
-                .ok_or_else(|| anyhow!("search requires query"))?
+                .ok_or_else(|| anyhow!("search requires query or regex"))?
                 .to_string();
-            let limit = get("limit").and_then(|v| v.as_u64()).map(|n| n as u32);
-            Ok(IpcRequest::Search { query, limit })
+            let fuzzy = match get("fuzzy").and_then(|v| v.as_bool()) {
+                None => FuzzyMode::Fallback,
+                Some(true) => FuzzyMode::Fill,
+                Some(false) => FuzzyMode::Off,
+            };
+            let rank_mode = match get("rank_mode") {
+                None | Some(Value::Null) => RankMode::Relevance,
+                Some(v) => match v.as_str() {
+                    Some("relevance") => RankMode::Relevance,
+                    Some("recent") => RankMode::Recent,
+                    _ => return Err(anyhow!("rank_mode must be \"relevance\" or \"recent\"")),
+                },
+            };
+            let starred_only = get("starred_only").and_then(|v| v.as_bool()).unwrap_or(false);
+            let (since, until) = parse_time_range(get("since"), get("until"))?;
+            let selection = parse_selection("selection", get("selection"))?;
+            let marker = |name: &str| -> Result<Option<String>> {
+                match get(name) {
+                    None | Some(Value::Null) => Ok(None),
+                    Some(v) => Ok(Some(v.as_str().ok_or_else(|| anyhow!("{name} must be a string"))?.to_string())),
+                }
+            };
+            Ok(IpcRequest::Search {
+                query,
+                limit,
+                filter: ItemFilter { starred_only, workspace, since, until, selection, ..Default::default() },
+                fuzzy,
+                rank_mode,
+                format: ItemFormat::parse(get)?,
+                snippet_open: marker("snippet_open")?,
+                snippet_close: marker("snippet_close")?,
+            })
         }
         "gallery" => {
             let limit = get("limit").and_then(|v| v.as_u64()).map(|n| n as u32);
-            Ok(IpcRequest::Gallery { limit })
+            let mime = match get("mime") {
+                None | Some(Value::Null) => None,
+                Some(v) => Some(v.as_str().ok_or_else(|| anyhow!("mime must be a string"))?.to_string()),
+            };
+            let dimension = |name: &str| -> Result<Option<u32>> {
+                match get(name) {
+                    None | Some(Value::Null) => Ok(None),
+                    Some(v) => v
+                        .as_u64()
+                        .map(|n| Some(n.min(u32::MAX as u64) as u32))
+                        .ok_or_else(|| anyhow!("{name} must be a non-negative integer")),
+                }
+            };
+            let filter = GalleryFilter {
+                mime,
+                min_width: dimension("min_width")?,
+                min_height: dimension("min_height")?,
+            };
+            Ok(IpcRequest::Gallery { limit, filter, format: ItemFormat::parse(get)? })
         }
-        "star" => {
+        "get" => {
             let id = get("id")
                 .and_then(|v| v.as_i64())
-                .ok_or_else(|| anyhow!("star requires id"))?;
+                .ok_or_else(|| anyhow!("get requires id"))?;
+            let format = ItemFormat { full_body: true, ..ItemFormat::parse(get)? };
+            Ok(IpcRequest::Get { id, format })
+        }
+        "star" => {
+            let ids = match get("ids") {
+                Some(ids_val) => {
+                    let ids_array = ids_val
+                        .as_array()
+                        .ok_or_else(|| anyhow!("ids must be an array"))?;
+                    if ids_array.is_empty() {
+                        return Err(anyhow!("ids array cannot be empty"));
+                    }
+                    ids_array
+                        .iter()
+                        .map(|v| v.as_i64().ok_or_else(|| anyhow!("ids must contain only integers")))
+                        .collect::<Result<Vec<i64>>>()?
+                }
+                None => vec![get("id")
+                    .and_then(|v| v.as_i64())
+                    .ok_or_else(|| anyhow!("star requires id or ids"))?],
+            };
             let value = get("value")
                 .and_then(|v| v.as_bool())
                 .ok_or_else(|| anyhow!("star requires value"))?;
-            Ok(IpcRequest::Star { id, value })
+            Ok(IpcRequest::Star { ids, value })
         }
         "copy" => {
             let id = get("id")
                 .and_then(|v| v.as_i64())
                 .ok_or_else(|| anyhow!("copy requires id"))?;
-            Ok(IpcRequest::Copy { id })
-        }
-        "delete" => {
-            let ids_val = get("ids")
-                .ok_or_else(|| anyhow!("delete requires ids"))?;
-            let ids_array = ids_val
-                .as_array()
-                .ok_or_else(|| anyhow!("ids must be an array"))?;
-            
-            if ids_array.is_empty() {
-                return Err(anyhow!("ids array cannot be empty"));
-            }
-            
-            let ids: Result<Vec<i64>> = ids_array
-                .iter()
-                .map(|v| v.as_i64().ok_or_else(|| anyhow!("ids must contain only integers")))
-                .collect();
-            
-            Ok(IpcRequest::Delete { ids: ids? })
+            let prefer_html = get("prefer_html").and_then(|v| v.as_bool()).unwrap_or(false);
+            let mime = match get("mime") {
+                None | Some(Value::Null) if prefer_html => CopyMime::Prefer("text/html".to_string()),
+                None | Some(Value::Null) => CopyMime::Canonical,
+                Some(_) if prefer_html => return Err(anyhow!("copy takes mime or prefer_html, not both")),
+                Some(v) => CopyMime::Exact(v.as_str().ok_or_else(|| anyhow!("mime must be a string"))?.to_string()),
+            };
+            Ok(IpcRequest::Copy { id, target: parse_selection("target", get("target"))?, mime })
+        }
+        "copy_plain" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("copy_plain requires id"))?;
+            Ok(IpcRequest::CopyPlain { id, target: parse_selection("target", get("target"))? })
+        }
+        "add_tag" | "remove_tag" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("{cmd} requires id"))?;
+            let tag = get("tag")
+                .and_then(|v| v.as_str())
+                .map(normalize_tag)
+                .ok_or_else(|| anyhow!("{cmd} requires tag"))?;
+            if tag.is_empty() {
+                return Err(anyhow!("tag cannot be empty"));
+            }
+            if cmd == "add_tag" {
+                Ok(IpcRequest::AddTag { id, tag })
+            } else {
+                Ok(IpcRequest::RemoveTag { id, tag })
+            }
+        }
+        "set_note" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("set_note requires id"))?;
+            let note = match get("note") {
+                None => return Err(anyhow!("set_note requires note (null to clear)")),
+                Some(Value::Null) => None,
+                Some(v) => {
+                    let note = v.as_str().ok_or_else(|| anyhow!("note must be a string or null"))?.trim();
+                    (!note.is_empty()).then(|| note.to_string())
+                }
+            };
+            Ok(IpcRequest::SetNote { id, note })
+        }
+        "rename" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("rename requires id"))?;
+            let title = get("title")
+                .and_then(|v| v.as_str())
+                .map(str::trim)
+                .filter(|title| !title.is_empty())
+                .ok_or_else(|| anyhow!("rename requires a non-empty title"))?;
+            Ok(IpcRequest::Rename { id, title: title.to_string() })
+        }
+        "set_kind" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("set_kind requires id"))?;
+            // Images and file lists are what they were copied as.
+            let kind = match get("kind").and_then(|v| v.as_str()) {
+                Some(k) if k != "files" && crate::clipboard::TEXT_KINDS.contains(&k) => k.to_string(),
+                Some(other) => return Err(anyhow!("kind must be one of text, url, color, code, not {other}")),
+                None => return Err(anyhow!("set_kind requires kind")),
+            };
+            let language = match get("language") {
+                None | Some(Value::Null) => None,
+                Some(v) => {
+                    let language = v.as_str().ok_or_else(|| anyhow!("language must be a string or null"))?.trim();
+                    (!language.is_empty()).then(|| language.to_lowercase())
+                }
+            };
+            if language.is_some() && kind != "code" {
+                return Err(anyhow!("language only applies to kind code"));
+            }
+            Ok(IpcRequest::SetKind { id, kind, language })
+        }
+        "touch" | "mark_used" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("{cmd} requires id"))?;
+            Ok(IpcRequest::Touch { id })
+        }
+        "history" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("history requires id"))?;
+            let limit = get("limit").and_then(|v| v.as_u64()).map(|n| n as u32);
+            Ok(IpcRequest::History { id, limit })
+        }
+        "find_similar" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("find_similar requires id"))?;
+            let threshold = match get("threshold") {
+                None | Some(Value::Null) => 0.8,
+                Some(v) => v
+                    .as_f64()
+                    .filter(|t| *t > 0.0 && *t <= 1.0)
+                    .ok_or_else(|| anyhow!("threshold must be a number in (0, 1]"))? as f32,
+            };
+            let limit = get("limit").and_then(|v| v.as_u64()).map(|n| n as u32).unwrap_or(20);
+            Ok(IpcRequest::FindSimilar { id, threshold, limit })
+        }
+        "paste" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("paste requires id"))?;
+            Ok(IpcRequest::Paste { id })
+        }
+        "delete" | "delete_items" => {
+            let ids = parse_ids(&cmd, get("ids"))?;
+            // `delete_items` used to delete unconditionally; keep that for old clients.
+            let force = cmd == "delete_items" || get("force").and_then(|v| v.as_bool()).unwrap_or(false);
+            let permanent = get("permanent").and_then(|v| v.as_bool()).unwrap_or(false);
+            Ok(IpcRequest::Delete { ids, force, permanent })
+        }
+        "restore" => Ok(IpcRequest::Restore { ids: parse_ids(&cmd, get("ids"))? }),
+        "trash" => {
+            let limit = get("limit").and_then(|v| v.as_u64()).map(|n| n as u32);
+            Ok(IpcRequest::Trash { limit, format: ItemFormat::parse(get)? })
+        }
+        "delete_image" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("delete_image requires id"))?;
+            Ok(IpcRequest::DeleteImage { id })
         }
         "delete_all_except_starred" => Ok(IpcRequest::DeleteAllExceptStarred),
-        "delete_items" => {
-            let ids_val = get("ids").ok_or_else(|| anyhow!("delete_items requires ids array"))?;
-            let ids_arr = ids_val.as_array().ok_or_else(|| anyhow!("ids must be an array"))?;
-            let mut ids: Vec<i64> = Vec::with_capacity(ids_arr.len());
-            for v in ids_arr {
-                let id = v.as_i64().ok_or_else(|| anyhow!("ids must contain integers"))?;
-                ids.push(id);
+        "clear" => {
+            let older_than_days = match get("older_than_days") {
+                None | Some(Value::Null) => None,
+                Some(v) => Some(
+                    v.as_u64()
+                        .map(|n| n.min(u32::MAX as u64) as u32)
+                        .ok_or_else(|| anyhow!("older_than_days must be a non-negative integer"))?,
+                ),
+            };
+            // 0 would mean "created before now": every unstarred item.
+            if older_than_days == Some(0) {
+                return Err(InvalidArgument("older_than_days must be at least 1".to_string()).into());
             }
-            Ok(IpcRequest::DeleteItems { ids })
+            let kind = match get("kind").and_then(|v| v.as_str()) {
+                Some(k) if k == "image" || crate::clipboard::TEXT_KINDS.contains(&k) => Some(k.to_string()),
+                Some(other) => return Err(anyhow!("unknown kind: {other}")),
+                None => None,
+            };
+            let include_starred = get("include_starred").and_then(|v| v.as_bool()).unwrap_or(false);
+            if older_than_days.is_none() && kind.is_none() && !include_starred {
+                return Err(anyhow!(
+                    "clear requires older_than_days or kind (or include_starred to wipe everything)"
+                ));
+            }
+            Ok(IpcRequest::Clear { older_than_days, kind, include_starred })
+        }
+        "delete_older_than" => {
+            let timestamp = get("timestamp")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("timestamp must be an integer"))?;
+            let include_starred = get("include_starred").and_then(|v| v.as_bool()).unwrap_or(false);
+            Ok(IpcRequest::DeleteOlderThan { timestamp, include_starred })
         }
+        "vacuum" => Ok(IpcRequest::Vacuum),
+        "rebuild_index" => Ok(IpcRequest::RebuildIndex),
+        "stats" => Ok(IpcRequest::Stats),
         "get_settings" => Ok(IpcRequest::GetSettings),
-        other => Err(anyhow!("unknown cmd: {other}")),
+        "ping" => Ok(IpcRequest::Ping),
+        "pause" => Ok(IpcRequest::Pause),
+        "resume" => Ok(IpcRequest::Resume),
+        "status" => Ok(IpcRequest::Status),
+        "incognito" => {
+            let minutes = match get("minutes").and_then(|v| v.as_u64()) {
+                Some(n) if n >= 1 => n.min(u32::MAX as u64) as u32,
+                _ => return Err(anyhow!("incognito requires minutes, a positive integer")),
+            };
+            Ok(IpcRequest::Incognito { minutes })
+        }
+        "import" => {
+            let path = get("path")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow!("import requires path"))?
+                .to_string();
+            Ok(IpcRequest::Import { path })
+        }
+        "export" => {
+            let path = get("path")
+                .and_then(|v| v.as_str())
+                .ok_or_else(|| anyhow!("export requires path"))?
+                .to_string();
+            let include_images = get("include_images").and_then(|v| v.as_bool()).unwrap_or(false);
+            Ok(IpcRequest::Export { path, include_images })
+        }
+        "reorder" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("reorder requires id"))?;
+            let position = get("position")
+                .and_then(|v| v.as_u64())
+                .ok_or_else(|| anyhow!("reorder requires position"))? as u32;
+            Ok(IpcRequest::Reorder { id, position })
+        }
+        "set_position" => {
+            let id = get("id")
+                .and_then(|v| v.as_i64())
+                .ok_or_else(|| anyhow!("set_position requires id"))?;
+            let position = match get("position") {
+                None | Some(Value::Null) => None,
+                Some(v) => match v.as_u64() {
+                    Some(n) if n >= 1 => Some(n as u32),
+                    _ => return Err(anyhow!("position must be a positive integer or null")),
+                },
+            };
+            Ok(IpcRequest::SetPosition { id, position })
+        }
+        "hello" | "handshake" => {
+            let client = get("client").and_then(|v| v.as_str()).map(|s| s.to_string());
+            let protocol = match get("protocol").or_else(|| get("client_version")) {
+                None | Some(Value::Null) => None,
+                Some(v) => Some(
+                    v.as_u64()
+                        .map(|n| n.min(u32::MAX as u64) as u32)
+                        .ok_or_else(|| anyhow!("protocol must be a non-negative integer"))?,
+                ),
+            };
+            Ok(IpcRequest::Hello { client, protocol })
+        }
+        "set_settings" => {
+            let mut patch = serde_json::Map::new();
+            for section in ["retention", "ui", "grid", "behavior"] {
+                if let Some(v) = get(section) {
+                    patch.insert(section.to_string(), v.clone());
+                }
+            }
+            if patch.is_empty() {
+                return Err(anyhow!("set_settings requires at least one of retention, ui, grid, behavior"));
+            }
+            Ok(IpcRequest::SetSettings { patch })
+        }
+        other => Err(anyhow!("cmd {other} is advertised but not implemented")),
+    }
+}
+
+/// Parses a non-empty `ids` array of integers.
+fn parse_ids(cmd: &str, ids: Option<&Value>) -> Result<Vec<i64>> {
+    let ids_array = ids
+        .ok_or_else(|| anyhow!("{cmd} requires ids"))?
+        .as_array()
+        .ok_or_else(|| anyhow!("ids must be an array"))?;
+    if ids_array.is_empty() {
+        return Err(anyhow!("ids array cannot be empty"));
     }
+    ids_array
+        .iter()
+        .map(|v| v.as_i64().ok_or_else(|| anyhow!("ids must contain only integers")))
+        .collect()
 }
 
 async fn dispatch_request(
-    conn: &Arc<Mutex<rusqlite::Connection>>,
-    cfg: &Arc<crate::config::Config>,
+    state: &Arc<AppState>,
+    session: &mut Session,
     req: IpcRequest,
 ) -> Result<IpcResponse<serde_json::Value>> {
+    let conn = &state.conn;
+    let preview_chars = state.config()?.behavior.body_preview_chars as usize;
+    let thumbs_dir = state.paths.thumbs_dir();
     let result = match req {
-        IpcRequest::List { limit, starred_only } => {
-            match list_items(conn, limit.unwrap_or(50), starred_only).await {
-                Ok(rows) => IpcResponse::ok(serde_json::to_value(rows)?),
-                Err(e) => IpcResponse::err(format!("Failed to list items: {}", e)),
+        IpcRequest::List { limit, filter, sort, format } => {
+            match list_items(conn, limit.unwrap_or(50), filter, sort).await {
+                Ok(rows) => IpcResponse::ok(format.render(&rows, preview_chars, &thumbs_dir)?),
+                Err(e) => IpcResponse::err(format!("Failed to list items: {}", e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Count { filter } => {
+            match count_items(conn, filter).await {
+                Ok(count) => IpcResponse::ok(serde_json::json!({ "count": count })),
+                Err(e) => IpcResponse::err(format!("Failed to count items: {}", e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Search { query, limit, filter, fuzzy, rank_mode, format, snippet_open, snippet_close } => {
+            let snippet = {
+                let cfg = state.config()?;
+                SnippetOptions {
+                    open: snippet_open.unwrap_or_else(|| cfg.search.snippet_open.clone()),
+                    close: snippet_close.unwrap_or_else(|| cfg.search.snippet_close.clone()),
+                    tokens: cfg.search.snippet_tokens.clamp(1, 64),
+                }
+            };
+            match search_items(conn, &query, limit.unwrap_or(50), filter, snippet, fuzzy, rank_mode).await {
+                Ok(rows) => IpcResponse::ok(format.render(&rows, preview_chars, &thumbs_dir)?),
+                Err(e) => IpcResponse::err(format!("Failed to search items: {}", e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::RegexSearch { regex, limit, filter, format } => {
+            match regex_search_items(conn, regex, limit.unwrap_or(50), filter).await {
+                Ok(rows) => IpcResponse::ok(format.render(&rows, preview_chars, &thumbs_dir)?),
+                Err(e) => IpcResponse::err(format!("Failed to search items: {}", e)).with_code(error_code(&e)),
             }
         }
-        IpcRequest::Search { query, limit } => {
-            match search_items(conn, &query, limit.unwrap_or(50)).await {
-                Ok(rows) => IpcResponse::ok(serde_json::to_value(rows)?),
-                Err(e) => IpcResponse::err(format!("Failed to search items: {}", e)),
+        IpcRequest::Gallery { limit, filter, format } => {
+            match gallery_items(conn, limit.unwrap_or(50), filter).await {
+                Ok(rows) => IpcResponse::ok(format.render(&rows, preview_chars, &thumbs_dir)?),
+                Err(e) => IpcResponse::err(format!("Failed to fetch gallery: {}", e)).with_code(error_code(&e)),
             }
         }
-        IpcRequest::Gallery { limit } => {
-            match gallery_items(conn, limit.unwrap_or(50)).await {
-                Ok(rows) => IpcResponse::ok(serde_json::to_value(rows)?),
-                Err(e) => IpcResponse::err(format!("Failed to fetch gallery: {}", e)),
+        IpcRequest::Get { id, format } => {
+            match get_item(conn, id).await {
+                Ok(item) => match format.render(&[item], preview_chars, &thumbs_dir)? {
+                    Value::Array(mut items) => IpcResponse::ok(items.remove(0)),
+                    other => IpcResponse::ok(other),
+                },
+                Err(e) => IpcResponse::err(format!("Failed to get item {}: {}", id, e)).with_code(error_code(&e)),
             }
         }
-        IpcRequest::Star { id, value } => {
-            match star_item(conn, id, value).await {
+        IpcRequest::Star { ids, value } => {
+            match star_items(conn, ids.clone(), value).await {
                 Ok(updated) => IpcResponse::ok(serde_json::json!({"updated": updated})),
-                Err(e) => IpcResponse::err(format!("Failed to star item {}: {}", id, e)),
+                Err(e) => IpcResponse::err(format!("Failed to star items {:?}: {}", ids, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Copy { id, target, mime } => {
+            match copy_to_clipboard(state, id, false, target, mime).await {
+                Ok(last_used) => IpcResponse::ok(serde_json::json!({"copied": true, "last_used": last_used})),
+                Err(e) => IpcResponse::err(format!("Failed to copy item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::CopyPlain { id, target } => {
+            match copy_to_clipboard(state, id, true, target, CopyMime::Canonical).await {
+                Ok(last_used) => IpcResponse::ok(serde_json::json!({"copied": true, "last_used": last_used})),
+                Err(e) => IpcResponse::err(format!("Failed to copy item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::AddTag { id, tag } => {
+            match set_tag(conn, id, tag, true).await {
+                Ok(tags) => IpcResponse::ok(serde_json::json!({"tags": tags})),
+                Err(e) => IpcResponse::err(format!("Failed to tag item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::RemoveTag { id, tag } => {
+            match set_tag(conn, id, tag, false).await {
+                Ok(tags) => IpcResponse::ok(serde_json::json!({"tags": tags})),
+                Err(e) => IpcResponse::err(format!("Failed to untag item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::SetNote { id, note } => {
+            match set_note(conn, id, note.clone()).await {
+                Ok(()) => IpcResponse::ok(serde_json::json!({"note": note})),
+                Err(e) => IpcResponse::err(format!("Failed to set note on item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Rename { id, title } => {
+            let max = state.config()?.behavior.title_max_chars as usize;
+            if title.chars().count() > max {
+                IpcResponse::err(format!("title must be at most {max} characters")).with_code(IpcError::InvalidRequest)
+            } else {
+                match rename_item(conn, id, title).await {
+                    Ok(updated) => IpcResponse::ok(serde_json::json!({ "updated": updated })),
+                    Err(e) => IpcResponse::err(format!("Failed to rename item {}: {}", id, e)).with_code(error_code(&e)),
+                }
+            }
+        }
+        IpcRequest::SetKind { id, kind, language } => {
+            match set_item_kind(conn, id, kind.clone(), language.clone()).await {
+                Ok(()) => IpcResponse::ok(serde_json::json!({ "kind": kind, "language": language })),
+                Err(e) => IpcResponse::err(format!("Failed to set kind of item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Touch { id } => {
+            match touch_item(conn, id).await {
+                Ok(last_used) => IpcResponse::ok(serde_json::json!({"updated": 1, "last_used": last_used})),
+                Err(e) => IpcResponse::err(format!("Failed to touch item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::History { id, limit } => {
+            match usage_history(conn, id, limit.unwrap_or(100)).await {
+                Ok(history) => IpcResponse::ok(serde_json::to_value(history)?),
+                Err(e) => IpcResponse::err(format!("Failed to fetch history of item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::FindSimilar { id, threshold, limit } => {
+            match find_similar(conn, id, threshold, limit).await {
+                Ok(matches) => IpcResponse::ok(serde_json::to_value(matches)?),
+                Err(e) => IpcResponse::err(format!("Failed to find items similar to {}: {}", id, e))
+                    .with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Paste { id } => {
+            let behavior = state.config()?.behavior.clone();
+            match copy_to_clipboard(state, id, false, None, CopyMime::Canonical).await {
+                Ok(last_used) => match simulate_paste(&behavior).await {
+                    Ok(_) => IpcResponse::ok(serde_json::json!({
+                        "copied": true,
+                        "pasted": true,
+                        "last_used": last_used
+                    })),
+                    Err(e) => IpcResponse::err(format!("Failed to paste item {}: {}", id, e)).with_code(error_code(&e)),
+                },
+                Err(e) => IpcResponse::err(format!("Failed to copy item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Delete { ids, force, permanent } => {
+            let trash = !permanent && state.config()?.behavior.trash_days.is_some();
+            match delete_items(conn, &state.paths.data_dir, ids, force, trash).await {
+                Ok(result) => IpcResponse::ok(serde_json::json!({
+                    "deleted": result.deleted,
+                    // Spelling used by the former `delete_items` response.
+                    "deleted_count": result.deleted,
+                    "skipped_starred": result.skipped_starred,
+                    "trashed": trash
+                })),
+                Err(e) => IpcResponse::err(format!("Failed to delete items: {}", e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Restore { ids } => {
+            match restore_items(conn, ids).await {
+                Ok(restored) => IpcResponse::ok(serde_json::json!({"restored": restored})),
+                Err(e) => IpcResponse::err(format!("Failed to restore items: {}", e)).with_code(error_code(&e)),
             }
         }
-        IpcRequest::Copy { id } => {
-            match copy_to_clipboard(conn, id).await {
-                Ok(_) => IpcResponse::ok(serde_json::json!({"copied": true})),
-                Err(e) => IpcResponse::err(format!("Failed to copy item {}: {}", id, e)),
+        IpcRequest::Trash { limit, format } => {
+            match trash_items(conn, limit.unwrap_or(50)).await {
+                Ok(rows) => IpcResponse::ok(format.render(&rows, preview_chars, &thumbs_dir)?),
+                Err(e) => IpcResponse::err(format!("Failed to list trash: {}", e)).with_code(error_code(&e)),
             }
         }
-        IpcRequest::Delete { ids } => {
-            match delete_items(conn, ids.clone()).await {
-                Ok(deleted) => IpcResponse::ok(serde_json::json!({"deleted": deleted})),
-                Err(e) => IpcResponse::err(format!("Failed to delete items: {}", e)),
+        IpcRequest::DeleteImage { id } => {
+            match delete_image(conn, &state.paths.data_dir, id).await {
+                Ok(removed) => IpcResponse::ok(serde_json::json!({"removed": removed})),
+                Err(e) => IpcResponse::err(format!("Failed to delete image of item {}: {}", id, e))
+                    .with_code(error_code(&e)),
             }
         }
         IpcRequest::DeleteAllExceptStarred => {
-            match delete_all_except_starred(conn).await {
+            match delete_all_except_starred(conn, &state.paths.data_dir).await {
                 Ok(result) => IpcResponse::ok(serde_json::json!({
                     "deleted_items": result.deleted_items,
                     "deleted_images": result.deleted_images
                 })),
-                Err(e) => IpcResponse::err(format!("Failed to delete all except starred: {}", e)),
+                Err(e) => IpcResponse::err(format!("Failed to delete all except starred: {}", e)).with_code(error_code(&e)),
             }
         }
-        IpcRequest::DeleteItems { ids } => {
-            let conn = conn.clone();
-            let ids_clone = ids.clone();
-            match tokio::task::spawn_blocking(move || {
-                let conn = conn.lock().map_err(|e| anyhow!("lock poisoned: {e}"))?;
-                let mut count: i64 = 0;
-                for id in ids_clone {
-                    match crate::retention::delete_item_and_files(&conn, id) {
-                        Ok(_) => { count += 1; },
-                        Err(err) => {
-                            tracing::warn!(error=%err, item_id=id, "failed to delete item by id");
+        IpcRequest::Clear { older_than_days, kind, include_starred } => {
+            match clear_items(conn, &state.paths.data_dir, older_than_days, kind, include_starred).await {
+                Ok(result) => IpcResponse::ok(serde_json::json!({
+                    "deleted_items": result.deleted_items,
+                    "deleted_images": result.deleted_images
+                })),
+                Err(e) => IpcResponse::err(format!("Failed to clear items: {}", e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::DeleteOlderThan { timestamp, include_starred } => {
+            match delete_older_than(conn, &state.paths.data_dir, timestamp, include_starred).await {
+                Ok(result) => IpcResponse::ok(serde_json::json!({
+                    "deleted_items": result.deleted_items,
+                    "deleted_images": result.deleted_images
+                })),
+                Err(e) => IpcResponse::err(format!("Failed to delete items: {}", e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Stats => {
+            match stats(state).await {
+                Ok(stats) => IpcResponse::ok(serde_json::to_value(stats)?),
+                Err(e) => IpcResponse::err(format!("Failed to compute stats: {}", e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Vacuum => {
+            match vacuum(conn, &state.paths).await {
+                Ok(result) => IpcResponse::ok(serde_json::json!({
+                    "size_before": result.size_before,
+                    "size_after": result.size_after,
+                    "orphans_removed": result.orphans_removed
+                })),
+                Err(e) => IpcResponse::err(format!("Failed to vacuum database: {}", e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::RebuildIndex => {
+            match rebuild_index(conn).await {
+                Ok(result) => IpcResponse::ok(serde_json::json!({
+                    "rows": result.rows,
+                    "elapsed_ms": result.elapsed_ms
+                })),
+                Err(e) => IpcResponse::err(format!("Failed to rebuild search index: {}", e))
+                    .with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::GetSettings => {
+            let cfg = state.config()?;
+            IpcResponse::ok(settings_json(state, &cfg))
+        }
+        IpcRequest::SetSettings { patch } => {
+            // Held across the write so concurrent patches apply one after the other.
+            let _writing = state.settings_write.lock().await;
+            let merged = state.config()?.merged_with(&patch);
+            match merged {
+                Ok(updated) => {
+                    let path = state.paths.config.clone();
+                    let to_save = updated.clone();
+                    match tokio::task::spawn_blocking(move || to_save.save(&path)).await {
+                        Ok(Ok(())) => {
+                            let mut cfg = state.config_mut()?;
+                            *cfg = updated;
+                            IpcResponse::ok(settings_json(state, &cfg))
                         }
+                        Ok(Err(e)) => IpcResponse::err(format!("Failed to save settings: {}", e)).with_code(error_code(&e)),
+                        Err(e) => IpcResponse::err(format!("Task failed: {}", e)),
                     }
                 }
-                Ok::<i64, anyhow::Error>(count)
+                Err(e) => IpcResponse::err(format!("Invalid settings: {}", e)).with_code(IpcError::InvalidRequest),
+            }
+        }
+        IpcRequest::Ping => {
+            IpcResponse::ok(serde_json::json!({
+                "pong": true,
+                "version": env!("CARGO_PKG_VERSION"),
+                "protocol": PROTOCOL_VERSION,
+                "uptime_secs": state.started_at.elapsed().as_secs(),
+                "db_path": state.paths.db.display().to_string()
+            }))
+        }
+        IpcRequest::Pause | IpcRequest::Resume => {
+            let paused = matches!(req, IpcRequest::Pause);
+            if state.is_paused() != paused {
+                info!("clipboard capture {}", if paused { "paused" } else { "resumed" });
+            }
+            state.set_paused(paused);
+            // Resuming means capturing now, incognito or not.
+            if !paused {
+                state.end_incognito();
+            }
+            IpcResponse::ok(serde_json::json!({ "paused": paused }))
+        }
+        IpcRequest::Status => {
+            let incognito = state.incognito_remaining();
+            IpcResponse::ok(serde_json::json!({
+                "paused": state.is_paused(),
+                "capturing": state.capturing(),
+                "incognito": incognito.is_some(),
+                "incognito_remaining_secs": incognito.map(|(remaining, _)| remaining.as_secs()),
+                "resume_at": incognito.map(|(_, resume_at)| resume_at)
+            }))
+        }
+        IpcRequest::Incognito { minutes } => {
+            let resume_at = state.start_incognito(std::time::Duration::from_secs(minutes as u64 * 60))?;
+            IpcResponse::ok(serde_json::json!({
+                "incognito": true,
+                "minutes": minutes,
+                "resume_at": resume_at
+            }))
+        }
+        IpcRequest::Import { path } => {
+            let conn = conn.clone();
+            let cfg = state.config()?.clone();
+            let paths = state.paths.clone();
+            match tokio::task::spawn_blocking(move || {
+                let conn = conn.lock().map_err(lock_poisoned)?;
+                crate::dump::import_items(&conn, std::path::Path::new(&path), &cfg, &paths)
             }).await {
-                Ok(Ok(deleted_count)) => IpcResponse::ok(serde_json::json!({
-                    "deleted_count": deleted_count
+                Ok(Ok(result)) => IpcResponse::ok(serde_json::json!({
+                    "imported": result.imported,
+                    "skipped": result.skipped,
+                    "errors": result.errors
                 })),
-                Ok(Err(e)) => IpcResponse::err(format!("Failed to delete items: {}", e)),
+                Ok(Err(e)) => IpcResponse::err(format!("Failed to import items: {}", e)).with_code(error_code(&e)),
                 Err(e) => IpcResponse::err(format!("Task failed: {}", e)),
             }
         }
-        IpcRequest::GetSettings => {
-            IpcResponse::ok(serde_json::json!({
-                "ui": {
-                    "width": cfg.ui.width,
-                    "height": cfg.ui.height,
-                    "anchor": cfg.ui.anchor,
-                    "opacity": cfg.ui.opacity,
-                    "blur": cfg.ui.blur
-                },
-                "grid": {
-                    "thumb_size": cfg.grid.thumb_size,
-                    "columns": cfg.grid.columns
-                },
-                "behavior": {
-                    "dedupe": cfg.behavior.dedupe
+        IpcRequest::Export { path, include_images } => {
+            let conn = conn.clone();
+            match tokio::task::spawn_blocking(move || {
+                let conn = conn.lock().map_err(lock_poisoned)?;
+                crate::dump::export_items(&conn, std::path::Path::new(&path), include_images)
+            }).await {
+                Ok(Ok(exported)) => IpcResponse::ok(serde_json::json!({ "exported": exported })),
+                Ok(Err(e)) => IpcResponse::err(format!("Failed to export items: {}", e)).with_code(error_code(&e)),
+                Err(e) => IpcResponse::err(format!("Task failed: {}", e)),
+            }
+        }
+        IpcRequest::Reorder { id, position } => {
+            match reorder_item(conn, id, position).await {
+                Ok(order) => IpcResponse::ok(serde_json::json!({ "order": order })),
+                Err(e) => IpcResponse::err(format!("Failed to reorder item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::SetPosition { id, position } => {
+            match set_item_position(conn, id, position).await {
+                Ok(updated) => IpcResponse::ok(serde_json::json!({
+                    "updated": updated,
+                    "position": position
+                })),
+                Err(e) => IpcResponse::err(format!("Failed to set position of item {}: {}", id, e)).with_code(error_code(&e)),
+            }
+        }
+        IpcRequest::Hello { client, protocol } => {
+            let requested = protocol.unwrap_or(PROTOCOL_VERSION);
+            if requested > PROTOCOL_VERSION {
+                let mut resp = IpcResponse::err(format!(
+                    "unsupported protocol version {requested}; highest supported is {PROTOCOL_VERSION}"
+                ))
+                .with_code(IpcError::InvalidRequest);
+                resp.data = Some(serde_json::json!({ "max_protocol": PROTOCOL_VERSION }));
+                resp
+            } else {
+                session.protocol_version = requested.max(1);
+                if let Some(client) = client {
+                    info!(client=%client, protocol = session.protocol_version, "client said hello");
                 }
-            }))
+                IpcResponse::ok(serde_json::json!({
+                    "protocol": session.protocol_version,
+                    "daemon_version": env!("CARGO_PKG_VERSION"),
+                    "capabilities": COMMANDS
+                }))
+            }
         }
     };
 
     Ok(result)
 }
 
+fn settings_json(state: &AppState, cfg: &Config) -> Value {
+    serde_json::json!({
+        "retention": {
+            "days": cfg.retention.days,
+            "delete_unstarred_only": cfg.retention.delete_unstarred_only,
+            "interval_secs": cfg.retention.interval_secs.unwrap_or(86400),
+            "per_kind": cfg.retention.per_kind
+        },
+        "ui": {
+            "width": cfg.ui.width,
+            "height": cfg.ui.height,
+            "anchor": cfg.ui.anchor.as_str(),
+            "opacity": cfg.ui.opacity,
+            "blur": cfg.ui.blur
+        },
+        "grid": {
+            "thumb_size": cfg.grid.thumb_size,
+            "columns": cfg.grid.columns,
+            "thumb_max_px": cfg.grid.thumb_max_px,
+            "thumb_format": cfg.grid.thumb_format.as_str(),
+            "thumb_quality": cfg.grid.thumb_quality
+        },
+        "behavior": {
+            "dedupe": cfg.behavior.dedupe,
+            "dedupe_window_secs": cfg.behavior.dedupe_window(),
+            "trash_days": cfg.behavior.trash_days,
+            "min_text_len": cfg.behavior.min_text_len,
+            "max_text_len": cfg.behavior.max_text_len
+        },
+        "capture": {
+            "paused": state.is_paused()
+        },
+        "paths": {
+            "config": state.paths.config,
+            "data_dir": state.paths.data_dir,
+            "db": state.paths.db,
+            "socket": state.paths.socket
+        }
+    })
+}
+
 struct DeleteAllResult {
     deleted_items: u64,
     deleted_images: u64,
 }
 
-async fn list_items(conn: &Arc<Mutex<rusqlite::Connection>>, limit: u32, starred_only: bool) -> Result<Vec<ItemSummary>> {
+async fn clear_items(
+    conn: &Arc<Mutex<rusqlite::Connection>>,
+    data_dir: &Path,
+    older_than_days: Option<u32>,
+    kind: Option<String>,
+    include_starred: bool,
+) -> Result<DeleteAllResult> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("system time error")?
+        .as_secs() as i64;
+
     let conn = conn.clone();
+    let data_dir = data_dir.to_path_buf();
     tokio::task::spawn_blocking(move || {
-        let conn = conn.lock().map_err(|e| anyhow!("lock poisoned: {e}"))?;
-        
-        let sql = if starred_only {
-            "SELECT id, title, body, created_at, updated_at, last_used, starred, hash,
-             EXISTS (SELECT 1 FROM images WHERE images.item_id = items.id) as has_image
-             FROM items WHERE starred = 1 ORDER BY last_used DESC LIMIT ?"
-        } else {
-            "SELECT id, title, body, created_at, updated_at, last_used, starred, hash,
-             EXISTS (SELECT 1 FROM images WHERE images.item_id = items.id) as has_image
-             FROM items ORDER BY starred DESC, last_used DESC LIMIT ?"
-        };
-        
-        let mut stmt = conn.prepare(sql)?;
+        let conn = conn.lock().map_err(lock_poisoned)?;
 
-        let rows = stmt
-            .query_map([limit], |row| {
-                let id: i64 = row.get(0)?;
-                let has_image: i64 = row.get(8)?;
-                let hash: Option<String> = row.get(7)?;
-                
-                let thumbnail_path = if has_image != 0 && hash.is_some() {
-                    let thumbs_dir = crate::db::default_data_dir()
-                        .map(|d| d.join("images/thumbs"))
-                        .ok();
-                    thumbs_dir.map(|d| d.join(format!("{}.png", hash.as_ref().unwrap())).to_string_lossy().to_string())
-                } else {
-                    None
-                };
-                
-                Ok(ItemSummary {
-                    id,
-                    title: row.get(1)?,
-                    body: row.get(2)?,
-                    created_at: row.get(3)?,
-                    updated_at: row.get(4)?,
-                    last_used: row.get(5)?,
-                    starred: row.get::<_, i64>(6)? != 0,
-                    hash,
-                    has_image: has_image != 0,
-                    thumbnail_path,
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
+        let mut sql = String::from("SELECT id FROM items WHERE 1 = 1");
+        let mut params: Vec<SqlValue> = Vec::new();
+        if let Some(days) = older_than_days {
+            sql.push_str(" AND created_at < ?");
+            params.push(SqlValue::Integer(now - days as i64 * 86400));
+        }
+        if let Some(kind) = kind {
+            sql.push_str(" AND kind = ?");
+            params.push(SqlValue::Text(kind));
+        }
+        if !include_starred {
+            sql.push_str(" AND starred = 0");
+        }
 
-        Ok(rows)
+        let ids: Vec<i64> = conn
+            .prepare(&sql)?
+            .query_map(rusqlite::params_from_iter(params), |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+
+        delete_in_transaction(&conn, &data_dir, &ids)
     })
     .await?
 }
 
-async fn search_items(conn: &Arc<Mutex<rusqlite::Connection>>, query: &str, limit: u32) -> Result<Vec<ItemSummary>> {
+/// Deletes `ids` in one transaction, then the files no remaining item
+/// shares. Files go only once the rows are gone for good.
+fn delete_in_transaction(conn: &rusqlite::Connection, data_dir: &Path, ids: &[i64]) -> Result<DeleteAllResult> {
+    let tx = conn.unchecked_transaction()?;
+    let mut deleted_images = 0;
+    let mut orphaned = Vec::new();
+    for id in ids {
+        deleted_images += tx.query_row("SELECT COUNT(*) FROM images WHERE item_id = ?", [id], |row| {
+            row.get::<_, i64>(0)
+        })? as u64;
+        orphaned.extend(crate::retention::delete_item(&tx, *id)?);
+    }
+    tx.commit()?;
+
+    for hash in &orphaned {
+        if let Err(err) = crate::retention::delete_image_files(data_dir, hash) {
+            warn!(hash, error=%err, "failed to delete image files");
+        }
+    }
+
+    Ok(DeleteAllResult {
+        deleted_items: ids.len() as u64,
+        deleted_images,
+    })
+}
+
+struct VacuumResult {
+    size_before: u64,
+    size_after: u64,
+    orphans_removed: u64,
+}
+
+/// Runs `VACUUM`, truncates the WAL and sweeps orphaned image files.
+/// Sizes are of the database file plus its WAL, in bytes.
+async fn vacuum(conn: &Arc<Mutex<rusqlite::Connection>>, paths: &Paths) -> Result<VacuumResult> {
     let conn = conn.clone();
-    let query = build_fts_prefix_query(query);
+    let (db_path, data_dir) = (paths.db.clone(), paths.data_dir.clone());
     tokio::task::spawn_blocking(move || {
-        let conn = conn.lock().map_err(|e| anyhow!("lock poisoned: {e}"))?;
-        let mut stmt = conn.prepare(
-            "SELECT items.id, items.title, items.body, items.created_at, items.updated_at, items.last_used, items.starred, items.hash,
-             EXISTS (SELECT 1 FROM images WHERE images.item_id = items.id) as has_image
-             FROM items_fts JOIN items ON items_fts.rowid = items.id
-             WHERE items_fts MATCH ?
-             ORDER BY rank
-             LIMIT ?",
-        )?;
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let size_before = database_size(&db_path);
 
-        let rows = stmt
-            .query_map((&query, limit), |row| {
-                let id: i64 = row.get(0)?;
-                let has_image: i64 = row.get(8)?;
-                let hash: Option<String> = row.get(7)?;
-                
-                let thumbnail_path = if has_image != 0 && hash.is_some() {
-                    let thumbs_dir = crate::db::default_data_dir()
-                        .map(|d| d.join("images/thumbs"))
-                        .ok();
-                    thumbs_dir.map(|d| d.join(format!("{}.png", hash.as_ref().unwrap())).to_string_lossy().to_string())
-                } else {
-                    None
-                };
-                
-                Ok(ItemSummary {
-                    id,
-                    title: row.get(1)?,
-                    body: row.get(2)?,
-                    created_at: row.get(3)?,
-                    updated_at: row.get(4)?,
-                    last_used: row.get(5)?,
-                    starred: row.get::<_, i64>(6)? != 0,
-                    hash,
-                    has_image: has_image != 0,
-                    thumbnail_path,
-                })
-            })?
-            .collect::<Result<Vec<_>, _>>()?;
+        conn.execute_batch("VACUUM").context("VACUUM failed")?;
+        conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))
+            .context("WAL checkpoint failed")?;
 
-        Ok(rows)
+        let orphans_removed = crate::retention::sweep_orphan_files(&conn, &data_dir)?;
+
+        Ok(VacuumResult {
+            size_before,
+            size_after: database_size(&db_path),
+            orphans_removed,
+        })
     })
     .await?
 }
 
+struct RebuildResult {
+    rows: u64,
+    elapsed_ms: u64,
+}
+
+/// Repopulates `items_fts` from `items`, for when the index has drifted after
+/// a manual edit or an interrupted write.
+async fn rebuild_index(conn: &Arc<Mutex<rusqlite::Connection>>) -> Result<RebuildResult> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let started = std::time::Instant::now();
+
+        conn.execute_batch("INSERT INTO items_fts(items_fts) VALUES('rebuild')")
+            .context("FTS rebuild failed")?;
+        let rows: i64 = conn.query_row("SELECT COUNT(*) FROM items", [], |row| row.get(0))?;
+        let elapsed_ms = started.elapsed().as_millis() as u64;
+
+        info!(rows, elapsed_ms, "rebuilt search index");
+        Ok(RebuildResult { rows: rows as u64, elapsed_ms })
+    })
+    .await?
+}
+
+#[derive(Debug, Serialize)]
+struct Stats {
+    /// Excludes trashed items, which are counted in `trashed_items`.
+    total_items: u64,
+    starred_items: u64,
+    trashed_items: u64,
+    /// Like `total_items`, excludes the trash.
+    image_items: u64,
+    /// Size of those items' original images, wherever they are stored.
+    image_bytes: u64,
+    oldest_created_at: Option<i64>,
+    newest_created_at: Option<i64>,
+    /// Database file plus its WAL.
+    db_size: u64,
+    /// Everything under `images/` (originals and thumbnails).
+    images_dir_size: u64,
+    /// Whether clipboard capture is paused.
+    paused: bool,
+}
+
+async fn stats(state: &AppState) -> Result<Stats> {
+    let conn = state.conn.clone();
+    let db_path = state.paths.db.clone();
+    let images_dir = state.paths.data_dir.join("images");
+    let paused = state.is_paused();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+
+        let (total_items, starred_items, oldest_created_at, newest_created_at) = conn.query_row(
+            "SELECT COUNT(*), COALESCE(SUM(starred = 1), 0), MIN(created_at), MAX(created_at) \
+             FROM items WHERE deleted_at IS NULL",
+            [],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+        let trashed_items: i64 =
+            conn.query_row("SELECT COUNT(*) FROM items WHERE deleted_at IS NOT NULL", [], |row| row.get(0))?;
+        // Images kept as files have no blob, so go by the recorded byte_size.
+        // COALESCE covers an empty table.
+        let (image_items, image_bytes) = conn.query_row(
+            "SELECT COUNT(DISTINCT images.item_id), COALESCE(SUM(images.byte_size), 0) \
+             FROM images JOIN items ON items.id = images.item_id WHERE items.deleted_at IS NULL",
+            [],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)),
+        )?;
+        drop(conn);
+
+        Ok(Stats {
+            total_items: total_items as u64,
+            starred_items: starred_items as u64,
+            trashed_items: trashed_items as u64,
+            image_items: image_items as u64,
+            image_bytes: image_bytes as u64,
+            oldest_created_at,
+            newest_created_at,
+            db_size: database_size(&db_path),
+            images_dir_size: dir_size(&images_dir),
+            paused,
+        })
+    })
+    .await?
+}
+
+/// Total size of the files under `dir`; unreadable entries are skipped.
+fn dir_size(dir: &std::path::Path) -> u64 {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return 0;
+    };
+    entries
+        .flatten()
+        .map(|entry| match entry.metadata() {
+            Ok(meta) if meta.is_dir() => dir_size(&entry.path()),
+            Ok(meta) => meta.len(),
+            Err(_) => 0,
+        })
+        .sum()
+}
+
+fn database_size(db_path: &std::path::Path) -> u64 {
+    let mut wal = db_path.as_os_str().to_owned();
+    wal.push("-wal");
+    [db_path.as_os_str().to_owned(), wal]
+        .iter()
+        .filter_map(|p| std::fs::metadata(p).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+/// Columns selected by every listing query, in the order `item_from_row` reads them.
+const ITEM_COLUMNS: &str = "items.id, items.title, items.body, items.created_at, items.updated_at, \
+     items.last_used, items.starred, items.hash, \
+     EXISTS (SELECT 1 FROM images WHERE images.item_id = items.id) as has_image, \
+     items.workspace, items.kind, items.selection, \
+     (SELECT group_concat(tag, char(31)) FROM tags WHERE tags.item_id = items.id) AS tags, \
+     (SELECT COUNT(*) FROM usage_log WHERE usage_log.item_id = items.id) AS use_count, \
+     items.note, items.deleted_at, \
+     (SELECT width FROM images WHERE images.item_id = items.id LIMIT 1) AS image_width, \
+     (SELECT height FROM images WHERE images.item_id = items.id LIMIT 1) AS image_height, \
+     (SELECT byte_size FROM images WHERE images.item_id = items.id LIMIT 1) AS image_byte_size, \
+     COALESCE((SELECT mime FROM images WHERE images.item_id = items.id LIMIT 1), \
+         CASE items.kind WHEN 'files' THEN 'text/uri-list' ELSE 'text/plain' END) \
+         || COALESCE(char(31) || (SELECT group_concat(mime, char(31)) FROM item_contents \
+             WHERE item_contents.item_id = items.id), '') AS mimes, \
+     items.source_app, items.language";
+
+fn item_from_row(row: &rusqlite::Row) -> rusqlite::Result<ItemSummary> {
+    let has_image = row.get::<_, i64>(8)? != 0;
+    let hash: Option<String> = row.get(7)?;
+
+    let mimes: Vec<String> = row.get::<_, String>(19)?.split('\u{1f}').map(str::to_string).collect();
+
+    let body: Option<String> = row.get(2)?;
+    let kind: Option<String> = row.get(10)?;
+    let files = match (kind.as_deref(), &body) {
+        (Some("files"), Some(body)) => crate::clipboard::parse_uri_list(body),
+        _ => Vec::new(),
+    };
+
+    Ok(ItemSummary {
+        id: row.get(0)?,
+        title: row.get(1)?,
+        body,
+        created_at: row.get(3)?,
+        updated_at: row.get(4)?,
+        last_used: row.get(5)?,
+        starred: row.get::<_, i64>(6)? != 0,
+        hash,
+        has_image,
+        // Filled in by `ItemFormat::render`, which knows where thumbnails live.
+        thumbnail_path: None,
+        workspace: row.get(9)?,
+        source_app: row.get(20)?,
+        kind,
+        language: row.get(21)?,
+        selection: row.get(11)?,
+        tags: split_tags(row.get(12)?),
+        use_count: row.get(13)?,
+        note: row.get(14)?,
+        deleted_at: row.get(15)?,
+        width: row.get(16)?,
+        height: row.get(17)?,
+        byte_size: row.get(18)?,
+        files,
+        // Filled in by `ItemFormat::render`, which checks the disk outside
+        // the database lock.
+        missing_files: Vec::new(),
+        has_html: mimes.iter().any(|mime| mime == "text/html"),
+        mimes,
+        fuzzy: false,
+        snippet: None,
+    })
+}
+
+/// Parses the optional `since`/`until` unix timestamps, rejecting `since > until`.
+fn parse_time_range(since: Option<&Value>, until: Option<&Value>) -> Result<(Option<i64>, Option<i64>)> {
+    let timestamp = |name: &str, v: Option<&Value>| -> Result<Option<i64>> {
+        match v {
+            None | Some(Value::Null) => Ok(None),
+            Some(v) => v
+                .as_i64()
+                .map(Some)
+                .ok_or_else(|| anyhow!("{name} must be a unix timestamp")),
+        }
+    };
+
+    let since = timestamp("since", since)?;
+    let until = timestamp("until", until)?;
+    if let (Some(s), Some(u)) = (since, until) {
+        if s > u {
+            return Err(anyhow!("since must not be after until"));
+        }
+    }
+
+    Ok((since, until))
+}
+
+fn normalize_tag(tag: &str) -> String {
+    tag.trim().to_lowercase()
+}
+
+/// Splits the `char(31)`-separated tag list selected by `ITEM_COLUMNS`.
+fn split_tags(joined: Option<String>) -> Vec<String> {
+    let mut tags: Vec<String> = joined
+        .map(|s| s.split('\u{1f}').map(str::to_string).collect())
+        .unwrap_or_default();
+    tags.sort();
+    tags
+}
+
+/// Adds or removes one tag and returns the item's resulting tags. Adding a
+/// tag the item already has is a no-op.
+async fn set_tag(conn: &Arc<Mutex<rusqlite::Connection>>, id: i64, tag: String, add: bool) -> Result<Vec<String>> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+
+        let exists: Option<i64> = conn
+            .query_row("SELECT id FROM items WHERE id = ?", [id], |row| row.get(0))
+            .optional()?;
+        if exists.is_none() {
+            return Err(anyhow::Error::new(ItemNotFound(id)));
+        }
+
+        if add {
+            conn.execute("INSERT OR IGNORE INTO tags (item_id, tag) VALUES (?, ?)", rusqlite::params![id, tag])?;
+        } else {
+            conn.execute("DELETE FROM tags WHERE item_id = ? AND tag = ?", rusqlite::params![id, tag])?;
+        }
+
+        let tags: Option<String> = conn.query_row(
+            "SELECT group_concat(tag, char(31)) FROM tags WHERE item_id = ?",
+            [id],
+            |row| row.get(0),
+        )?;
+        Ok(split_tags(tags))
+    })
+    .await?
+}
+
+async fn set_note(conn: &Arc<Mutex<rusqlite::Connection>>, id: i64, note: Option<String>) -> Result<()> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let updated = conn
+            .execute("UPDATE items SET note = ? WHERE id = ?", rusqlite::params![note, id])
+            .context("failed to update note")?;
+        if updated == 0 {
+            return Err(anyhow::Error::new(ItemNotFound(id)));
+        }
+        Ok(())
+    })
+    .await?
+}
+
+/// The items_au trigger re-indexes the new title.
+async fn rename_item(conn: &Arc<Mutex<rusqlite::Connection>>, id: i64, title: String) -> Result<u64> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let updated = conn
+            .execute("UPDATE items SET title = ? WHERE id = ?", rusqlite::params![title, id])
+            .context("failed to update title")?;
+        if updated == 0 {
+            return Err(anyhow::Error::new(ItemNotFound(id)));
+        }
+        Ok(updated as u64)
+    })
+    .await?
+}
+
+async fn set_item_kind(
+    conn: &Arc<Mutex<rusqlite::Connection>>,
+    id: i64,
+    kind: String,
+    language: Option<String>,
+) -> Result<()> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let current: Option<String> = conn
+            .query_row("SELECT kind FROM items WHERE id = ?", [id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| anyhow::Error::new(ItemNotFound(id)))?;
+        if let Some(kind @ ("image" | "files")) = current.as_deref() {
+            return Err(InvalidArgument(format!("{kind} items keep their kind")).into());
+        }
+        conn.execute(
+            "UPDATE items SET kind = ?, language = ? WHERE id = ?",
+            rusqlite::params![kind, language, id],
+        )
+        .context("failed to update kind")?;
+        Ok(())
+    })
+    .await?
+}
+
+/// Explicit `list` ordering, replacing the default pinned/starred/recency order.
+#[derive(Debug, Clone, Copy)]
+pub struct ListSort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SortKey {
+    LastUsed,
+    CreatedAt,
+    Title,
+    Size,
+    UseCount,
+}
+
+impl SortKey {
+    fn parse(s: &str) -> Option<Self> {
+        match s {
+            "last_used" => Some(SortKey::LastUsed),
+            "created_at" => Some(SortKey::CreatedAt),
+            "title" => Some(SortKey::Title),
+            "size" => Some(SortKey::Size),
+            "use_count" => Some(SortKey::UseCount),
+            _ => None,
+        }
+    }
+
+    /// SQL expression ordered by; never built from client input.
+    fn column(self) -> &'static str {
+        match self {
+            SortKey::LastUsed => "items.last_used",
+            SortKey::CreatedAt => "items.created_at",
+            SortKey::Title => "items.title COLLATE NOCASE",
+            SortKey::Size => {
+                // Images kept only as files have no blob; byte_size is always recorded.
+                "COALESCE((SELECT byte_size FROM images WHERE images.item_id = items.id), length(items.body))"
+            }
+            SortKey::UseCount => "(SELECT COUNT(*) FROM usage_log WHERE usage_log.item_id = items.id)",
+        }
+    }
+}
+
+/// Parses `sort`/`order`. `order` alone sorts by `last_used`; `sort` alone
+/// defaults to descending.
+fn parse_sort(sort: Option<&Value>, order: Option<&Value>) -> Result<Option<ListSort>> {
+    if sort.is_none() && order.is_none() {
+        return Ok(None);
+    }
+
+    let key = match sort {
+        None => SortKey::LastUsed,
+        Some(v) => {
+            let name = v.as_str().ok_or_else(|| anyhow!("sort must be a string"))?;
+            SortKey::parse(name).ok_or_else(|| {
+                anyhow!("unknown sort key: {name} (expected last_used, created_at, title, size or use_count)")
+            })?
+        }
+    };
+    let descending = match order.map(|v| v.as_str()) {
+        None | Some(Some("desc")) => true,
+        Some(Some("asc")) => false,
+        Some(_) => return Err(anyhow!("order must be \"asc\" or \"desc\"")),
+    };
+
+    Ok(Some(ListSort { key, descending }))
+}
+
+/// How timestamps in item listings are rendered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TimeFormat {
+    /// Unix seconds, as stored.
+    #[default]
+    Unix,
+    /// RFC 3339 strings in the daemon's local timezone.
+    Rfc3339,
+}
+
+/// Presentation options for `list`, `search`, `gallery` and `get` responses.
+#[derive(Debug, Clone, Default)]
+pub struct ItemFormat {
+    pub time_format: TimeFormat,
+    /// Keys to keep in each item; `None` keeps all of them.
+    pub fields: Option<Vec<String>>,
+    /// Send `body` as is. Otherwise it is replaced by `body_preview`, cut to
+    /// `behavior.body_preview_chars`, and `is_truncated`. Naming `body` in
+    /// `fields` also opts in.
+    pub full_body: bool,
+}
+
+/// Every key an `ItemSummary` can serialize.
+const ITEM_FIELDS: &[&str] = &[
+    "id",
+    "title",
+    "body",
+    "body_preview",
+    "is_truncated",
+    "created_at",
+    "updated_at",
+    "last_used",
+    "starred",
+    "hash",
+    "has_image",
+    "thumbnail_path",
+    "workspace",
+    "source_app",
+    "kind",
+    "language",
+    "selection",
+    "tags",
+    "note",
+    "use_count",
+    "deleted_at",
+    "width",
+    "height",
+    "byte_size",
+    "files",
+    "missing_files",
+    "mimes",
+    "has_html",
+    "fuzzy",
+    "snippet",
+];
+
+impl ItemFormat {
+    const TIME_FIELDS: [&'static str; 4] = ["created_at", "updated_at", "last_used", "deleted_at"];
+
+    fn parse<'a>(get: impl Fn(&str) -> Option<&'a Value>) -> Result<Self> {
+        let time_format = match get("time_format") {
+            None | Some(Value::Null) => TimeFormat::Unix,
+            Some(v) => match v.as_str() {
+                Some("unix") => TimeFormat::Unix,
+                Some("rfc3339") => TimeFormat::Rfc3339,
+                _ => return Err(anyhow!("time_format must be \"unix\" or \"rfc3339\"")),
+            },
+        };
+        let fields = match get("fields") {
+            None | Some(Value::Null) => None,
+            Some(v) => {
+                let names = v.as_array().ok_or_else(|| anyhow!("fields must be an array"))?;
+                let fields = names
+                    .iter()
+                    .map(|name| match name.as_str() {
+                        Some(name) if ITEM_FIELDS.contains(&name) => Ok(name.to_string()),
+                        Some(name) => Err(anyhow!("unknown field: {name}")),
+                        None => Err(anyhow!("fields must contain only strings")),
+                    })
+                    .collect::<Result<Vec<String>>>()?;
+                Some(fields)
+            }
+        };
+        let full_body = get("full_body").and_then(|v| v.as_bool()).unwrap_or(false)
+            || fields.as_ref().is_some_and(|f| f.iter().any(|name| name == "body"));
+        Ok(Self { time_format, fields, full_body })
+    }
+
+    /// Serializes `rows`, previewing bodies unless `full_body` is set,
+    /// rewriting timestamp fields when RFC 3339 was requested and dropping
+    /// keys not listed in `fields`.
+    fn render(&self, rows: &[ItemSummary], preview_chars: usize, thumbs_dir: &Path) -> Result<Value> {
+        let mut value = serde_json::to_value(rows)?;
+        for (row, item) in rows.iter().zip(value.as_array_mut().into_iter().flatten()) {
+            if let (Some(hash), true, Some(map)) = (&row.hash, row.has_image, item.as_object_mut()) {
+                if let Some(path) = crate::clipboard::find_thumbnail(thumbs_dir, hash) {
+                    map.insert("thumbnail_path".to_string(), Value::String(path.to_string_lossy().into_owned()));
+                }
+            }
+            if let (false, Some(map)) = (row.files.is_empty(), item.as_object_mut()) {
+                let missing: Vec<&String> = row.files.iter().filter(|path| !Path::new(path).exists()).collect();
+                if !missing.is_empty() {
+                    map.insert("missing_files".to_string(), serde_json::to_value(missing)?);
+                }
+            }
+            if let (false, Some(map)) = (self.full_body, item.as_object_mut()) {
+                let (preview, truncated) = match map.remove("body") {
+                    Some(Value::String(body)) => {
+                        let (preview, truncated) = crate::transform::truncate_chars(&body, preview_chars);
+                        (Value::String(preview.to_string()), truncated)
+                    }
+                    _ => (Value::Null, false),
+                };
+                map.insert("body_preview".to_string(), preview);
+                map.insert("is_truncated".to_string(), Value::Bool(truncated));
+            }
+            if let (Some(fields), Some(map)) = (&self.fields, item.as_object_mut()) {
+                map.retain(|key, _| fields.contains(key));
+            }
+            if self.time_format == TimeFormat::Rfc3339 {
+                for field in Self::TIME_FIELDS {
+                    if let Some(ts) = item.get_mut(field) {
+                        if let Some(secs) = ts.as_i64() {
+                            *ts = Value::String(format_rfc3339(secs));
+                        }
+                    }
+                }
+            }
+        }
+        Ok(value)
+    }
+}
+
+fn format_rfc3339(secs: i64) -> String {
+    use chrono::TimeZone;
+    match chrono::Local.timestamp_opt(secs, 0).single() {
+        Some(dt) => dt.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        None => secs.to_string(),
+    }
+}
+
+/// Optional predicates shared by `list` and `search`. Trashed items never match.
+#[derive(Debug, Default)]
+pub struct ItemFilter {
+    pub starred_only: bool,
+    pub workspace: Option<String>,
+    pub kind: Option<String>,
+    /// Inclusive `created_at` bounds, in unix seconds.
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    /// Normalized tag the item must carry.
+    pub tag: Option<String>,
+    /// Only items captured from this selection.
+    pub selection: Option<Selection>,
+}
+
+impl ItemFilter {
+    /// The filter keys `list` and `count` share.
+    fn parse<'a>(get: impl Fn(&str) -> Option<&'a Value>) -> Result<Self> {
+        let starred_only = get("starred_only").and_then(|v| v.as_bool()).unwrap_or(false);
+        let workspace = get("workspace").and_then(|v| v.as_str()).map(|s| s.to_string());
+        // `all` is the explicit spelling of no kind filter, for picker tabs.
+        let kind = match get("kind").and_then(|v| v.as_str()) {
+            Some("all") | None => None,
+            Some(k) if k == "image" || crate::clipboard::TEXT_KINDS.contains(&k) => Some(k.to_string()),
+            Some(other) => return Err(anyhow!("unknown kind: {other}")),
+        };
+        let (since, until) = parse_time_range(get("since"), get("until"))?;
+        let tag = get("tag").and_then(|v| v.as_str()).map(normalize_tag);
+        let selection = parse_selection("selection", get("selection"))?;
+        Ok(Self { starred_only, workspace, kind, since, until, tag, selection })
+    }
+
+    /// Appends ` AND ...` clauses for this filter to `sql`, pushing the bound values.
+    fn apply(&self, sql: &mut String, params: &mut Vec<SqlValue>) {
+        sql.push_str(" AND items.deleted_at IS NULL");
+        if self.starred_only {
+            sql.push_str(" AND items.starred = 1");
+        }
+        if let Some(workspace) = &self.workspace {
+            // Items captured where the workspace couldn't be determined stay visible everywhere.
+            sql.push_str(" AND (items.workspace = ? OR items.workspace IS NULL)");
+            params.push(SqlValue::Text(workspace.clone()));
+        }
+        if let Some(kind) = &self.kind {
+            sql.push_str(" AND items.kind = ?");
+            params.push(SqlValue::Text(kind.clone()));
+        }
+        if let Some(since) = self.since {
+            sql.push_str(" AND items.created_at >= ?");
+            params.push(SqlValue::Integer(since));
+        }
+        if let Some(until) = self.until {
+            sql.push_str(" AND items.created_at <= ?");
+            params.push(SqlValue::Integer(until));
+        }
+        if let Some(tag) = &self.tag {
+            sql.push_str(" AND EXISTS (SELECT 1 FROM tags WHERE tags.item_id = items.id AND tags.tag = ?)");
+            params.push(SqlValue::Text(tag.clone()));
+        }
+        if let Some(selection) = self.selection {
+            sql.push_str(" AND items.selection = ?");
+            params.push(SqlValue::Text(selection.as_str().to_string()));
+        }
+    }
+}
+
+async fn list_items(
+    conn: &Arc<Mutex<rusqlite::Connection>>,
+    limit: u32,
+    filter: ItemFilter,
+    sort: Option<ListSort>,
+) -> Result<Vec<ItemSummary>> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+
+        let mut sql = format!("SELECT {ITEM_COLUMNS} FROM items WHERE 1 = 1");
+        let mut params: Vec<SqlValue> = Vec::new();
+        filter.apply(&mut sql, &mut params);
+
+        match sort {
+            Some(sort) => {
+                let dir = if sort.descending { "DESC" } else { "ASC" };
+                sql.push_str(&format!(" ORDER BY {} {dir}, items.id {dir} LIMIT ?", sort.key.column()));
+            }
+            // Positioned items come first; only starred items carry a sort_order.
+            None => sql.push_str(
+                " ORDER BY items.position ASC NULLS LAST, items.starred DESC, \
+                 items.sort_order ASC NULLS LAST, items.last_used DESC LIMIT ?",
+            ),
+        }
+        params.push(SqlValue::Integer(limit as i64));
+
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let rows = stmt
+            .query_map(rusqlite::params_from_iter(params), item_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(rows)
+    })
+    .await?
+}
+
+async fn count_items(conn: &Arc<Mutex<rusqlite::Connection>>, filter: ItemFilter) -> Result<u64> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+
+        let mut sql = String::from("SELECT COUNT(*) FROM items WHERE 1 = 1");
+        let mut params: Vec<SqlValue> = Vec::new();
+        filter.apply(&mut sql, &mut params);
+
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let count: i64 = stmt.query_row(rusqlite::params_from_iter(params), |row| row.get(0))?;
+        Ok(count as u64)
+    })
+    .await?
+}
+
+/// When `search` adds edit-distance matches to the full-text results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuzzyMode {
+    /// Only when the full-text query matched nothing; the default, so typos
+    /// still find something.
+    Fallback,
+    /// Whenever the full-text results don't fill `limit` (`fuzzy: true`).
+    Fill,
+    /// Never (`fuzzy: false`).
+    Off,
+}
+
+/// Ordering of full-text `search` results.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RankMode {
+    /// `bm25` adjusted by `RANK_WEIGHTS`, so starred and recently used items
+    /// rise above stale matches.
+    Relevance,
+    /// Plain `bm25` text relevance, the order before boosts existed.
+    Recent,
+}
+
+/// Boosts applied on top of `bm25` in `RankMode::Relevance`. `bm25` scores
+/// are negative with lower being better, so boosts are subtracted.
+#[derive(Debug, Clone, Copy)]
+pub struct RankWeights {
+    pub starred_bonus: f64,
+    /// Bonus for an item used just now, halving after `recency_half_life_days`.
+    pub recency_bonus: f64,
+    pub recency_half_life_days: f64,
+}
+
+pub const RANK_WEIGHTS: RankWeights = RankWeights {
+    starred_bonus: 2.0,
+    recency_bonus: 3.0,
+    recency_half_life_days: 7.0,
+};
+
+impl RankWeights {
+    /// `ORDER BY` expression and its bound values, given the current time.
+    fn order_by(&self, now: i64) -> (&'static str, Vec<SqlValue>) {
+        (
+            "bm25(items_fts) - ? * items.starred \
+             - ? / (1.0 + MAX(? - COALESCE(items.last_used, items.created_at), 0) / 86400.0 / ?)",
+            vec![
+                SqlValue::Real(self.starred_bonus),
+                SqlValue::Real(self.recency_bonus),
+                SqlValue::Integer(now),
+                SqlValue::Real(self.recency_half_life_days),
+            ],
+        )
+    }
+}
+
+pub struct SnippetOptions {
+    pub open: String,
+    pub close: String,
+    pub tokens: u32,
+}
+
+async fn search_items(
+    conn: &Arc<Mutex<rusqlite::Connection>>,
+    query: &str,
+    limit: u32,
+    filter: ItemFilter,
+    snippet: SnippetOptions,
+    fuzzy: FuzzyMode,
+    rank_mode: RankMode,
+) -> Result<Vec<ItemSummary>> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("system time error")?
+        .as_secs() as i64;
+    let conn = conn.clone();
+    let fuzzy_tokens = crate::fuzzy::tokenize(query);
+    let query = build_fts_prefix_query(query);
+    if query.is_empty() && filter.starred_only {
+        // Nothing left to match (e.g. only punctuation): show the starred list instead.
+        return list_items(&conn, limit, filter, None).await;
+    }
+    if query.is_empty() {
+        // An empty MATCH is an FTS5 syntax error, e.g. for `""`.
+        return Ok(Vec::new());
+    }
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+
+        // Column -1 lets FTS5 pick whichever of title/body matched best; image-only
+        // items have an empty body, so an empty snippet is reported as null.
+        let mut sql = format!(
+            "SELECT {ITEM_COLUMNS}, NULLIF(snippet(items_fts, -1, ?, ?, '…', ?), '') AS snippet \
+             FROM items_fts JOIN items ON items_fts.rowid = items.id \
+             WHERE items_fts MATCH ?"
+        );
+        let mut params: Vec<SqlValue> = vec![
+            SqlValue::Text(snippet.open),
+            SqlValue::Text(snippet.close),
+            SqlValue::Integer(snippet.tokens as i64),
+            SqlValue::Text(query),
+        ];
+        filter.apply(&mut sql, &mut params);
+        match rank_mode {
+            RankMode::Relevance => {
+                let (order_by, order_params) = RANK_WEIGHTS.order_by(now);
+                sql.push_str(&format!(" ORDER BY {order_by} LIMIT ?"));
+                params.extend(order_params);
+            }
+            RankMode::Recent => sql.push_str(" ORDER BY rank LIMIT ?"),
+        }
+        params.push(SqlValue::Integer(limit as i64));
+
+        let mut stmt = conn.prepare_cached(&sql)?;
+        let mut rows = stmt
+            .query_map(rusqlite::params_from_iter(params), |row| {
+                let mut item = item_from_row(row)?;
+                item.snippet = row.get("snippet")?;
+                Ok(item)
+            })?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let run_fuzzy = match fuzzy {
+            FuzzyMode::Fallback => rows.is_empty(),
+            FuzzyMode::Fill => rows.len() < limit as usize,
+            FuzzyMode::Off => false,
+        };
+        if run_fuzzy {
+            let want = limit as usize - rows.len();
+            let extra = fuzzy_search(&conn, &fuzzy_tokens, &filter, &rows, want)?;
+            rows.extend(extra);
+        }
+
+        Ok(rows)
+    })
+    .await?
+}
+
+/// Compiled size cap for `regex` searches, so a pathological pattern can't
+/// exhaust memory.
+const REGEX_SIZE_LIMIT: usize = 1 << 20;
+/// Rows fetched per query while scanning for a `regex` search.
+const REGEX_BATCH_SIZE: i64 = 500;
+/// A `regex` search stops after this many rows or this long, whichever comes
+/// first, and returns what it found so far.
+const REGEX_SCAN_LIMIT: u64 = 100_000;
+const REGEX_TIME_LIMIT: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// Scans items newest first in batches, keeping those whose title, body or
+/// note matches `regex`, until `limit` matches or a scan cap is reached.
+async fn regex_search_items(
+    conn: &Arc<Mutex<rusqlite::Connection>>,
+    regex: regex::Regex,
+    limit: u32,
+    filter: ItemFilter,
+) -> Result<Vec<ItemSummary>> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let started = std::time::Instant::now();
+
+        let mut base_sql =
+            String::from("SELECT items.id, items.created_at, items.title, items.body, items.note FROM items WHERE 1 = 1");
+        let mut base_params: Vec<SqlValue> = Vec::new();
+        filter.apply(&mut base_sql, &mut base_params);
+
+        let mut matched: Vec<i64> = Vec::new();
+        let mut scanned: u64 = 0;
+        // Keyset pagination on (created_at, id), so each batch is an index range.
+        let mut after: Option<(i64, i64)> = None;
+        'scan: loop {
+            let mut sql = base_sql.clone();
+            let mut params = base_params.clone();
+            if let Some((created_at, id)) = after {
+                sql.push_str(" AND (items.created_at, items.id) < (?, ?)");
+                params.push(SqlValue::Integer(created_at));
+                params.push(SqlValue::Integer(id));
+            }
+            sql.push_str(" ORDER BY items.created_at DESC, items.id DESC LIMIT ?");
+            params.push(SqlValue::Integer(REGEX_BATCH_SIZE));
+
+            let batch: Vec<(i64, i64, bool)> = conn
+                .prepare(&sql)?
+                .query_map(rusqlite::params_from_iter(params), |row| {
+                    let mut hit = false;
+                    for column in 2..=4 {
+                        if let Some(text) = row.get_ref(column)?.as_str_or_null()? {
+                            hit = hit || regex.is_match(text);
+                        }
+                    }
+                    Ok((row.get(0)?, row.get(1)?, hit))
+                })?
+                .collect::<Result<_, _>>()?;
+            let Some(&(last_id, last_created_at, _)) = batch.last() else {
+                break;
+            };
+            after = Some((last_created_at, last_id));
+
+            for (id, _, hit) in batch {
+                scanned += 1;
+                if hit {
+                    matched.push(id);
+                    if matched.len() >= limit as usize {
+                        break 'scan;
+                    }
+                }
+            }
+
+            if scanned >= REGEX_SCAN_LIMIT || started.elapsed() >= REGEX_TIME_LIMIT {
+                warn!(scanned, elapsed_ms = started.elapsed().as_millis() as u64, "regex search stopped early");
+                break;
+            }
+        }
+
+        let mut stmt = conn.prepare_cached(&format!("SELECT {ITEM_COLUMNS} FROM items WHERE id = ?"))?;
+        let rows = matched
+            .into_iter()
+            .filter_map(|id| stmt.query_row([id], item_from_row).optional().transpose())
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    })
+    .await?
+}
+
+/// Most recent text items considered by a fuzzy pass.
+const FUZZY_CANDIDATES: i64 = 2000;
+
+/// Ranks recent text items by edit-distance similarity to the query, skipping
+/// anything already in `found`.
+fn fuzzy_search(
+    conn: &rusqlite::Connection,
+    tokens: &[String],
+    filter: &ItemFilter,
+    found: &[ItemSummary],
+    want: usize,
+) -> Result<Vec<ItemSummary>> {
+    if tokens.is_empty() || want == 0 {
+        return Ok(Vec::new());
+    }
+
+    let mut sql = format!("SELECT {ITEM_COLUMNS} FROM items WHERE items.kind != 'image'");
+    let mut params: Vec<SqlValue> = Vec::new();
+    filter.apply(&mut sql, &mut params);
+    sql.push_str(" ORDER BY items.last_used DESC LIMIT ?");
+    params.push(SqlValue::Integer(FUZZY_CANDIDATES));
+
+    let mut stmt = conn.prepare_cached(&sql)?;
+    let candidates = stmt
+        .query_map(rusqlite::params_from_iter(params), item_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut scored: Vec<(f32, ItemSummary)> = candidates
+        .into_iter()
+        .filter(|item| !found.iter().any(|f| f.id == item.id))
+        .filter_map(|item| {
+            let text = format!(
+                "{} {}",
+                item.title.as_deref().unwrap_or(""),
+                item.body.as_deref().unwrap_or("")
+            );
+            let score = crate::fuzzy::score(tokens, &text);
+            (score >= crate::fuzzy::MATCH_THRESHOLD).then_some((score, item))
+        })
+        .collect();
+
+    scored.sort_by(|a, b| b.0.total_cmp(&a.0));
+    Ok(scored
+        .into_iter()
+        .take(want)
+        .map(|(_, item)| ItemSummary { fuzzy: true, ..item })
+        .collect())
+}
+
+/// Turns user input into an FTS5 query: bare words become prefix terms and
+/// balanced double-quoted spans become phrases, e.g. `"connection refused" ngi`
+/// gives `"connection refused" "ngi"*`. Words are reduced to letters, digits,
+/// `_` and `-` inside quotes too, so input can't inject FTS operators. Every
+/// term is emitted quoted, since FTS5 barewords can't contain `-`. An
+/// unmatched quote is treated as a separator.
 fn build_fts_prefix_query(input: &str) -> String {
-    let mut tokens: Vec<String> = Vec::new();
+    let mut terms: Vec<String> = Vec::new();
+
+    let quotes = input.matches('"').count();
+    // With an odd count, the last quote has no partner.
+    let balanced_end = if quotes.is_multiple_of(2) { input.len() } else { input.rfind('"').unwrap_or(input.len()) };
+    let (quoted, rest) = input.split_at(balanced_end);
+
+    for (idx, part) in quoted.split('"').enumerate() {
+        if idx % 2 == 1 {
+            let words = fts_words(part);
+            if !words.is_empty() {
+                terms.push(format!("\"{}\"", words.join(" ")));
+            }
+        } else {
+            terms.extend(fts_words(part).into_iter().map(|w| format!("\"{w}\"*")));
+        }
+    }
+    terms.extend(fts_words(rest).into_iter().map(|w| format!("\"{w}\"*")));
+
+    terms.truncate(12);
+    terms.join(" ")
+}
+
+/// Lowercased words of `text`, split on anything but letters, digits, `_` and `-`.
+fn fts_words(text: &str) -> Vec<String> {
+    let mut words: Vec<String> = Vec::new();
     let mut current = String::new();
 
-    for ch in input.chars() {
-        let keep = ch.is_ascii_alphanumeric() || ch == '_' || ch == '-';
+    for ch in text.chars() {
+        // Unicode-aware so accented, Cyrillic and CJK text survives; FTS
+        // operators and punctuation still split tokens.
+        let keep = ch.is_alphanumeric() || ch == '_' || ch == '-';
         if keep {
-            current.push(ch.to_ascii_lowercase());
+            current.extend(ch.to_lowercase());
         } else if !current.is_empty() {
-            tokens.push(std::mem::take(&mut current));
+            words.push(std::mem::take(&mut current));
         }
     }
 
     if !current.is_empty() {
-        tokens.push(current);
+        words.push(current);
     }
 
-    if tokens.len() > 12 {
-        tokens.truncate(12);
-    }
+    words
+}
 
-    tokens
-        .into_iter()
-        .filter(|t| !t.is_empty())
-        .map(|t| format!("{t}*"))
-        .collect::<Vec<_>>()
-        .join(" ")
+/// Optional predicates on the image row for `gallery`.
+#[derive(Debug, Default)]
+pub struct GalleryFilter {
+    pub mime: Option<String>,
+    /// Images stored without dimensions never satisfy these.
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
 }
 
-async fn gallery_items(conn: &Arc<Mutex<rusqlite::Connection>>, limit: u32) -> Result<Vec<ItemSummary>> {
+async fn gallery_items(
+    conn: &Arc<Mutex<rusqlite::Connection>>,
+    limit: u32,
+    filter: GalleryFilter,
+) -> Result<Vec<ItemSummary>> {
     let conn = conn.clone();
     tokio::task::spawn_blocking(move || {
-        let conn = conn.lock().map_err(|e| anyhow!("lock poisoned: {e}"))?;
-        let mut stmt = conn.prepare(
-            "SELECT items.id, items.title, items.body, items.created_at, items.updated_at, items.last_used, items.starred, items.hash,
-             1 as has_image
+        let conn = conn.lock().map_err(lock_poisoned)?;
+
+        let mut image_sql = String::from("SELECT 1 FROM images WHERE images.item_id = items.id");
+        let mut params: Vec<SqlValue> = Vec::new();
+        if let Some(mime) = filter.mime {
+            image_sql.push_str(" AND images.mime = ?");
+            params.push(SqlValue::Text(mime));
+        }
+        if let Some(min_width) = filter.min_width {
+            image_sql.push_str(" AND images.width >= ?");
+            params.push(SqlValue::Integer(min_width as i64));
+        }
+        if let Some(min_height) = filter.min_height {
+            image_sql.push_str(" AND images.height >= ?");
+            params.push(SqlValue::Integer(min_height as i64));
+        }
+        params.push(SqlValue::Integer(limit as i64));
+
+        let mut stmt = conn.prepare_cached(&format!(
+            "SELECT {ITEM_COLUMNS}
              FROM items
-             WHERE EXISTS (SELECT 1 FROM images WHERE images.item_id = items.id)
+             WHERE items.deleted_at IS NULL AND EXISTS ({image_sql})
              ORDER BY items.last_used DESC
-             LIMIT ?",
-        )?;
+             LIMIT ?"
+        ))?;
 
         let rows = stmt
-            .query_map([limit], |row| {
-                let id: i64 = row.get(0)?;
-                let hash: Option<String> = row.get(7)?;
-                
-                let thumbnail_path = if hash.is_some() {
-                    let thumbs_dir = crate::db::default_data_dir()
-                        .map(|d| d.join("images/thumbs"))
-                        .ok();
-                    thumbs_dir.map(|d| d.join(format!("{}.png", hash.as_ref().unwrap())).to_string_lossy().to_string())
-                } else {
-                    None
-                };
-                
-                Ok(ItemSummary {
-                    id,
-                    title: row.get(1)?,
-                    body: row.get(2)?,
-                    created_at: row.get(3)?,
-                    updated_at: row.get(4)?,
-                    last_used: row.get(5)?,
-                    starred: row.get::<_, i64>(6)? != 0,
-                    hash,
-                    has_image: true,
-                    thumbnail_path,
-                })
+            .query_map(rusqlite::params_from_iter(params), item_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(rows)
+    })
+    .await?
+}
+
+/// Returns the number of rows updated, which is lower than `ids.len()` when
+/// some ids no longer exist.
+async fn star_items(conn: &Arc<Mutex<rusqlite::Connection>>, ids: Vec<i64>, value: bool) -> Result<u64> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let tx = conn.unchecked_transaction()?;
+
+        let placeholders = (0..ids.len()).map(|_| "?").collect::<Vec<_>>().join(",");
+        // Unstarred items drop out of the manual ordering.
+        let sql = format!(
+            "UPDATE items SET starred = ?1, sort_order = CASE WHEN ?1 = 1 THEN sort_order END \
+             WHERE id IN ({placeholders})"
+        );
+        let params = std::iter::once(SqlValue::Integer(value as i64))
+            .chain(ids.iter().map(|id| SqlValue::Integer(*id)));
+        let updated = tx.execute(&sql, rusqlite::params_from_iter(params))? as u64;
+
+        tx.commit()?;
+        Ok(updated)
+    })
+    .await?
+}
+
+/// Pins an item to a fixed list position, or clears it with `None`. If the
+/// position is taken, the contiguous run of pinned items from there is shifted
+/// down by one.
+async fn set_item_position(conn: &Arc<Mutex<rusqlite::Connection>>, id: i64, position: Option<u32>) -> Result<u64> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let tx = conn.unchecked_transaction()?;
+
+        let updated = tx.execute("UPDATE items SET position = NULL WHERE id = ?", [id])? as u64;
+        if updated == 0 {
+            return Err(anyhow::Error::new(ItemNotFound(id)));
+        }
+
+        if let Some(position) = position {
+            let taken: Vec<(i64, i64)> = {
+                let mut stmt = tx.prepare(
+                    "SELECT id, position FROM items WHERE position >= ? ORDER BY position ASC",
+                )?;
+                let rows = stmt.query_map([position], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect::<Result<Vec<_>, _>>()?
+            };
+
+            let shifted: Vec<i64> = (position as i64..)
+                .zip(taken)
+                .take_while(|(expected, (_, other_pos))| other_pos == expected)
+                .map(|(_, (other_id, _))| other_id)
+                .collect();
+
+            // Shift from the bottom up so positions never collide mid-update.
+            for other_id in shifted.iter().rev() {
+                tx.execute("UPDATE items SET position = position + 1 WHERE id = ?", [other_id])?;
+            }
+
+            tx.execute("UPDATE items SET position = ? WHERE id = ?", rusqlite::params![position, id])?;
+        }
+
+        tx.commit()?;
+        Ok(updated)
+    })
+    .await?
+}
+
+/// Why `id` can't be reordered: it doesn't exist, or isn't a starred item.
+fn not_starred(conn: &rusqlite::Connection, id: i64) -> anyhow::Error {
+    match conn.query_row("SELECT EXISTS (SELECT 1 FROM items WHERE id = ?)", [id], |row| row.get(0)) {
+        Ok(true) => InvalidArgument(format!("item {id} is not starred")).into(),
+        Ok(false) => ItemNotFound(id).into(),
+        Err(err) => err.into(),
+    }
+}
+
+/// Moves a starred item to `position` (1-based) among starred items and
+/// renumbers all of them, closing any gaps or ties. Returns the new order.
+async fn reorder_item(conn: &Arc<Mutex<rusqlite::Connection>>, id: i64, position: u32) -> Result<Vec<i64>> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let tx = conn.unchecked_transaction()?;
+
+        let mut order: Vec<i64> = {
+            let mut stmt = tx.prepare(
+                "SELECT id FROM items WHERE starred = 1 AND deleted_at IS NULL \
+                 ORDER BY sort_order ASC NULLS LAST, last_used DESC",
+            )?;
+            let rows = stmt.query_map([], |row| row.get(0))?;
+            rows.collect::<Result<Vec<i64>, _>>()?
+        };
+
+        let current = order
+            .iter()
+            .position(|&other| other == id)
+            .ok_or_else(|| not_starred(&tx, id))?;
+        order.remove(current);
+
+        let target = (position.max(1) as usize - 1).min(order.len());
+        order.insert(target, id);
+
+        {
+            let mut stmt = tx.prepare("UPDATE items SET sort_order = ? WHERE id = ?")?;
+            for (idx, item_id) in order.iter().enumerate() {
+                stmt.execute(rusqlite::params![idx as i64 + 1, item_id])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(order)
+    })
+    .await?
+}
+
+/// Parses the selection named by arg `name` (a copy `target` or a `selection`
+/// filter); absent or null means none was given.
+fn parse_selection(name: &str, value: Option<&Value>) -> Result<Option<Selection>> {
+    match value {
+        None | Some(Value::Null) => Ok(None),
+        Some(v) => v
+            .as_str()
+            .and_then(Selection::parse)
+            .map(Some)
+            .ok_or_else(|| anyhow!("{name} must be \"clipboard\" or \"primary\"")),
+    }
+}
+
+/// Restores item `id` to `target` or else the selection the item was
+/// captured from. With `plain`, text bodies that look like HTML are stripped
+/// to plain text first. `mime` may pick another stored representation. With
+/// the native backend every stored representation is offered at once unless
+/// `plain` or an exact `mime` narrows it down; otherwise the one picked is
+/// piped to `wl-copy`. Once the selection is set the item counts as used;
+/// returns its new `last_used`.
+async fn copy_to_clipboard(
+    state: &AppState,
+    id: i64,
+    plain: bool,
+    target: Option<Selection>,
+    mime: CopyMime,
+) -> Result<i64> {
+    let native = state.data_control.get().cloned();
+    let offer_all = native.is_some() && !plain && !matches!(mime, CopyMime::Exact(_));
+    let mime = if offer_all { CopyMime::Canonical } else { mime };
+
+    let conn = state.conn.clone();
+    let (item, selection, extra) = tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+
+        let selection: Option<String> = conn
+            .query_row("SELECT selection FROM items WHERE id = ?", [id], |row| row.get(0))
+            .optional()?;
+        let selection = target
+            .or_else(|| selection.as_deref().and_then(Selection::parse))
+            .unwrap_or(Selection::Clipboard);
+
+        let extra: Vec<(String, Vec<u8>)> = if offer_all {
+            let mut stmt = conn.prepare("SELECT mime, bytes FROM item_contents WHERE item_id = ? ORDER BY mime")?;
+            let rows = stmt.query_map([id], |row| Ok((row.get(0)?, row.get(1)?)))?;
+            rows.collect::<rusqlite::Result<_>>()?
+        } else {
+            Vec::new()
+        };
+
+        let (wanted, strict) = match &mime {
+            CopyMime::Canonical => (None, false),
+            CopyMime::Exact(mime) => (Some(mime), true),
+            CopyMime::Prefer(mime) => (Some(mime), false),
+        };
+        if let Some(mime) = wanted {
+            let stored: Option<Vec<u8>> = conn
+                .query_row(
+                    "SELECT bytes FROM item_contents WHERE item_id = ? AND mime = ?",
+                    rusqlite::params![id, mime],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(bytes) = stored {
+                return Ok((CopyPayload::Typed { mime: mime.clone(), bytes }, selection, extra));
+            }
+        }
+        let missing = |canonical: &str| wanted.filter(|m| strict && *m != canonical).cloned();
+
+        let image_row: Option<(String, Option<Vec<u8>>, Option<String>)> = conn
+            .query_row(
+                "SELECT mime, bytes, path FROM images WHERE item_id = ? LIMIT 1",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+
+        if let Some((image_mime, bytes, path)) = image_row {
+            if let Some(mime) = missing(&image_mime) {
+                return Err(anyhow!("item {id} has no {mime} representation"));
+            }
+            let bytes = crate::clipboard::load_image_bytes(bytes, path.as_deref())?;
+            return Ok((CopyPayload::Typed { mime: image_mime, bytes }, selection, extra));
+        }
+
+        let text: Option<(String, Option<String>, Option<Vec<u8>>)> = conn
+            .query_row(
+                "SELECT body, kind, raw FROM items WHERE id = ?",
+                [id],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()?;
+
+        if let Some((body, kind, raw)) = text {
+            // `delete_image` keeps the row but leaves nothing to paste.
+            if kind.as_deref() == Some("image") {
+                return Err(InvalidArgument(format!("item {id} no longer has its image")).into());
+            }
+            // File lists only paste as files under their own mime.
+            if kind.as_deref() == Some("files") {
+                if let Some(mime) = missing(crate::clipboard::FILES_MIME) {
+                    return Err(anyhow!("item {id} has no {mime} representation"));
+                }
+                let mime = crate::clipboard::FILES_MIME.to_string();
+                return Ok((CopyPayload::Typed { mime, bytes: body.into_bytes() }, selection, extra));
+            }
+            if let Some(mime) = missing("text/plain") {
+                return Err(anyhow!("item {id} has no {mime} representation"));
+            }
+            // Text that isn't UTF-8 goes back byte for byte; `body` is lossy.
+            if let Some(bytes) = raw {
+                return Ok((CopyPayload::Typed { mime: "text/plain".to_string(), bytes }, selection, extra));
+            }
+            if plain && crate::transform::looks_like_html(&body) {
+                return Ok((CopyPayload::Text { body: crate::transform::html_to_text(&body) }, selection, extra));
+            }
+            return Ok((CopyPayload::Text { body }, selection, extra));
+        }
+
+        Err(anyhow::Error::new(ItemNotFound(id)))
+    })
+    .await
+    .map_err(|e| anyhow!("database task failed: {}", e))??;
+
+    // The watcher will see this write as a clipboard change; let it skip the
+    // echo. It hashes text the way `behavior.normalize_text` says.
+    let normalize = state.config()?.behavior.normalize_text;
+    state.suppress_hash(match &item {
+        CopyPayload::Typed { bytes, .. } => crate::clipboard::compute_hash(bytes),
+        CopyPayload::Text { body } => crate::clipboard::text_hash(body.as_bytes(), normalize),
+    });
+
+    match native {
+        Some(native) => {
+            let canonical = match item {
+                CopyPayload::Typed { mime, bytes } => (mime, bytes),
+                CopyPayload::Text { body } => ("text/plain".to_string(), body.into_bytes()),
+            };
+            let extra = extra.into_iter().filter(|(mime, _)| *mime != canonical.0).collect::<Vec<_>>();
+            let reps = std::iter::once(canonical).chain(extra).collect();
+            native.publish(selection, reps)?;
+        }
+        None => wl_copy(&state.wl_copy_command, selection, item).await?,
+    }
+
+    // Restored items count as used, so they move up the recency-ordered list.
+    // Done only after the selection was set, so a failed copy isn't recorded.
+    touch_item(&state.conn, id).await
+}
+
+/// Sets `selection` to `item` by piping it to `command`, the program and
+/// leading arguments to run as `wl-copy`.
+async fn wl_copy(command: &[String], selection: Selection, item: CopyPayload) -> Result<()> {
+    let (program, leading) = command.split_first().ok_or_else(|| anyhow!("no wl-copy command configured"))?;
+    let (mime, bytes) = match item {
+        CopyPayload::Typed { mime, bytes } => (Some(mime), bytes),
+        CopyPayload::Text { body } => (None, body.into_bytes()),
+    };
+    let what = mime.as_deref().unwrap_or("text");
+
+    let mut cmd = Command::new(program);
+    cmd.args(leading).args(selection.wl_args());
+    if let Some(mime) = &mime {
+        cmd.arg("-t").arg(mime);
+    }
+    let mut child = match cmd
+        .stdin(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(anyhow!("wl-copy not found - install wl-clipboard package"));
+        }
+        Err(e) => return Err(e).with_context(|| format!("failed to spawn wl-copy for {what}")),
+    };
+
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin
+            .write_all(&bytes)
+            .await
+            .with_context(|| format!("failed to write {what} data to wl-copy"))?;
+        drop(stdin); // Explicitly close stdin
+    }
+
+    let output = child.wait_with_output().await.context("failed to wait on wl-copy")?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        return Err(anyhow!("wl-copy failed: {}", stderr));
+    }
+
+    Ok(())
+}
+
+async fn get_item(conn: &Arc<Mutex<rusqlite::Connection>>, id: i64) -> Result<ItemSummary> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        conn.query_row(&format!("SELECT {ITEM_COLUMNS} FROM items WHERE id = ?"), [id], item_from_row)
+            .optional()?
+            .ok_or_else(|| anyhow::Error::new(ItemNotFound(id)))
+    })
+    .await?
+}
+
+#[derive(Debug, Serialize)]
+struct UsageHistory {
+    id: i64,
+    use_count: u64,
+    /// Newest first, at most `limit` entries.
+    used_at: Vec<i64>,
+}
+
+async fn usage_history(conn: &Arc<Mutex<rusqlite::Connection>>, id: i64, limit: u32) -> Result<UsageHistory> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let exists: bool = conn.query_row("SELECT EXISTS (SELECT 1 FROM items WHERE id = ?)", [id], |row| row.get(0))?;
+        if !exists {
+            return Err(anyhow::Error::new(ItemNotFound(id)));
+        }
+
+        let use_count: u64 =
+            conn.query_row("SELECT COUNT(*) FROM usage_log WHERE item_id = ?", [id], |row| row.get(0))?;
+        let used_at = conn
+            .prepare("SELECT used_at FROM usage_log WHERE item_id = ? ORDER BY used_at DESC LIMIT ?")?
+            .query_map(rusqlite::params![id, limit], |row| row.get(0))?
+            .collect::<rusqlite::Result<Vec<i64>>>()?;
+
+        Ok(UsageHistory { id, use_count, used_at })
+    })
+    .await?
+}
+
+#[derive(Debug, Serialize)]
+struct SimilarItem {
+    id: i64,
+    score: f32,
+}
+
+/// Most recent candidates compared by `find_similar`.
+const SIMILAR_SCAN_LIMIT: u32 = 5000;
+
+/// Scores other text items against item `id` by token-set overlap. Only
+/// items whose body is within 2x the length of the target's are compared,
+/// newest first and at most `SIMILAR_SCAN_LIMIT` of them.
+async fn find_similar(
+    conn: &Arc<Mutex<rusqlite::Connection>>,
+    id: i64,
+    threshold: f32,
+    limit: u32,
+) -> Result<Vec<SimilarItem>> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+
+        let body: String = conn
+            .query_row("SELECT COALESCE(body, '') FROM items WHERE id = ? AND kind != 'image'", [id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| anyhow::Error::new(ItemNotFound(id)))?;
+        let target: std::collections::HashSet<String> = crate::fuzzy::tokenize(&body).into_iter().collect();
+        let len = body.chars().count() as i64;
+
+        let mut stmt = conn.prepare(
+            "SELECT id, body FROM items \
+             WHERE id != ? AND kind != 'image' AND deleted_at IS NULL AND length(body) BETWEEN ? AND ? \
+             ORDER BY created_at DESC LIMIT ?",
+        )?;
+        let mut matches: Vec<SimilarItem> = stmt
+            .query_map(rusqlite::params![id, len / 2, len * 2, SIMILAR_SCAN_LIMIT], |row| {
+                Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?))
             })?
-            .collect::<Result<Vec<_>, _>>()?;
+            .filter_map(|r| r.ok())
+            .filter_map(|(other_id, other)| {
+                let tokens = crate::fuzzy::tokenize(&other).into_iter().collect();
+                let score = crate::fuzzy::token_set_similarity(&target, &tokens);
+                (score >= threshold).then_some(SimilarItem { id: other_id, score })
+            })
+            .collect();
 
-        Ok(rows)
+        matches.sort_by(|a, b| b.score.total_cmp(&a.score));
+        matches.truncate(limit as usize);
+        Ok(matches)
     })
     .await?
 }
 
-async fn star_item(conn: &Arc<Mutex<rusqlite::Connection>>, id: i64, value: bool) -> Result<u64> {
+/// Sets `last_used` to now, records the use in `usage_log` and returns it.
+async fn touch_item(conn: &Arc<Mutex<rusqlite::Connection>>, id: i64) -> Result<i64> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("system time error")?
+        .as_secs() as i64;
+
     let conn = conn.clone();
     tokio::task::spawn_blocking(move || {
-        let conn = conn.lock().map_err(|e| anyhow!("lock poisoned: {e}"))?;
-        let updated = conn.execute(
-            "UPDATE items SET starred = ? WHERE id = ?",
-            rusqlite::params![if value { 1 } else { 0 }, id],
-        )? as u64;
-        Ok(updated)
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let updated = conn
+            .execute("UPDATE items SET last_used = ? WHERE id = ?", [now, id])
+            .context("failed to update last_used")?;
+        if updated == 0 {
+            return Err(anyhow::Error::new(ItemNotFound(id)));
+        }
+        crate::db::record_usage(&conn, id, now)?;
+        Ok(now)
     })
     .await?
 }
 
-async fn copy_to_clipboard(conn: &Arc<Mutex<rusqlite::Connection>>, id: i64) -> Result<()> {
-    if let Err(_) = tokio::process::Command::new("which")
-        .arg("wl-copy")
-        .output()
-        .await
-    {
-        return Err(anyhow!("wl-copy not found - install wl-clipboard package"));
-    }
+/// Paste keystroke simulators tried in order when `paste_command` is unset.
+const PASTE_TOOLS: &[&str] = &["wtype -M ctrl -k v -m ctrl", "ydotool key ctrl+v"];
 
-    let conn = conn.clone();
-    let item = tokio::task::spawn_blocking(move || {
-        let conn = conn.lock().map_err(|e| anyhow!("lock poisoned: {e}"))?;
+/// Sends a paste keystroke to the focused window after `paste_delay_ms`.
+/// Without a `paste_command`, each of `PASTE_TOOLS` is tried in turn until
+/// one succeeds, so `ydotool` covers compositors `wtype` can't drive.
+/// Fails with "no paste tool found" when none of the tools is installed, so
+/// clients can fall back to a plain copy.
+async fn simulate_paste(behavior: &crate::config::Behavior) -> Result<()> {
+    tokio::time::sleep(std::time::Duration::from_millis(behavior.paste_delay_ms as u64)).await;
 
-        let image_row: Option<(String, Vec<u8>)> = conn
-            .query_row(
-                "SELECT mime, bytes FROM images WHERE item_id = ? LIMIT 1",
-                [id],
-                |row| Ok((row.get(0)?, row.get(1)?)),
-            )
-            .optional()?;
+    match &behavior.paste_command {
+        Some(cmd) => run_paste_commands(&[cmd.as_str()]).await,
+        None => run_paste_commands(PASTE_TOOLS).await,
+    }
+}
+
+/// Runs `commands` in order until one succeeds. Fails with the last tool's
+/// error, or `PasteToolUnavailable` when none of them is installed.
+async fn run_paste_commands(commands: &[&str]) -> Result<()> {
+    let mut last_error = None;
+    for command in commands {
+        let mut argv = command.split_whitespace();
+        let Some(program) = argv.next() else {
+            continue;
+        };
 
-        if let Some((mime, bytes)) = image_row {
-            return Ok(CopyPayload::Image { mime, bytes });
+        match Command::new(program).args(argv).output().await {
+            Ok(output) if output.status.success() => return Ok(()),
+            Ok(output) => {
+                let stderr = String::from_utf8_lossy(&output.stderr);
+                debug!(program, stderr=%stderr.trim(), "paste tool failed, trying the next one");
+                last_error = Some(anyhow!("{} failed: {}", program, stderr.trim()));
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => last_error = Some(anyhow!("failed to run {}: {}", program, e)),
         }
+    }
 
-        let text: Option<String> = conn
-            .query_row(
-                "SELECT body FROM items WHERE id = ?",
-                [id],
-                |row| row.get(0),
-            )
-            .optional()?;
+    if let Some(err) = last_error {
+        return Err(err);
+    }
+    let tried = commands
+        .iter()
+        .filter_map(|c| c.split_whitespace().next())
+        .map(str::to_string)
+        .collect();
+    Err(PasteToolUnavailable(tried).into())
+}
 
-        if let Some(body) = text {
-            return Ok(CopyPayload::Text { body });
-        }
+/// Which representation `copy` restores.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CopyMime {
+    /// The text body or image the item was recorded from.
+    Canonical,
+    /// Exactly this one of the item's `mimes`; fails if it has no such one.
+    Exact(String),
+    /// This mime when the item has it, else the canonical representation.
+    Prefer(String),
+}
 
-        Err(anyhow!("item with id {} not found", id))
-    })
-    .await
-    .map_err(|e| anyhow!("database task failed: {}", e))??;
+enum CopyPayload {
+    /// An image or a stored representation, offered as `mime`.
+    Typed { mime: String, bytes: Vec<u8> },
+    Text { body: String },
+}
 
-    match item {
-        CopyPayload::Image { mime, bytes } => {
-            let mut child = Command::new("wl-copy")
-                .arg("-t")
-                .arg(&mime)
-                .stdin(std::process::Stdio::piped())
-                .stderr(std::process::Stdio::piped())
-                .spawn()
-                .context("failed to spawn wl-copy for image")?;
-
-            if let Some(mut stdin) = child.stdin.take() {
-                stdin
-                    .write_all(&bytes)
-                    .await
-                    .context("failed to write image data to wl-copy")?;
-                drop(stdin); // Explicitly close stdin
-            }
-
-            let output = child.wait_with_output().await.context("failed to wait on wl-copy")?;
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                return Err(anyhow!("wl-copy failed: {}", stderr));
+struct DeleteResult {
+    deleted: u64,
+    /// Requested ids that were kept because they are starred.
+    skipped_starred: Vec<i64>,
+}
+
+/// Deletes `ids` in one transaction, then removes their originals and
+/// thumbnails best-effort. Starred items are kept and reported unless `force`.
+/// With `trash`, items only get `deleted_at` set and keep their files until
+/// the retention sweep purges them.
+async fn delete_items(
+    conn: &Arc<Mutex<rusqlite::Connection>>,
+    data_dir: &Path,
+    ids: Vec<i64>,
+    force: bool,
+    trash: bool,
+) -> Result<DeleteResult> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("system time error")?
+        .as_secs() as i64;
+
+    let conn = conn.clone();
+    let data_dir = data_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+
+        let tx = conn.unchecked_transaction()?;
+
+        let placeholders = (0..ids.len()).map(|_| "?").collect::<Vec<_>>().join(",");
+        let rows: Vec<(i64, Option<String>, bool)> = tx
+            .prepare(&format!("SELECT id, hash, starred FROM items WHERE id IN ({placeholders})"))?
+            .query_map(rusqlite::params_from_iter(ids.iter()), |row| {
+                Ok((row.get(0)?, row.get(1)?, row.get::<_, i64>(2)? != 0))
+            })?
+            .collect::<rusqlite::Result<_>>()?;
+
+        let mut skipped_starred = Vec::new();
+        let mut hashes = Vec::new();
+        let mut deleted = 0;
+        for (id, hash, starred) in rows {
+            if starred && !force {
+                skipped_starred.push(id);
+                continue;
             }
+            if trash {
+                // Items already in the trash keep their original deletion time.
+                deleted += tx.execute(
+                    "UPDATE items SET deleted_at = COALESCE(deleted_at, ?) WHERE id = ?",
+                    [now, id],
+                )? as u64;
+                continue;
+            }
+            tx.execute("DELETE FROM images WHERE item_id = ?", [id])?;
+            deleted += tx.execute("DELETE FROM items WHERE id = ?", [id])? as u64;
+            hashes.extend(hash);
         }
-        CopyPayload::Text { body } => {
-            let mut child = Command::new("wl-copy")
-                .stdin(std::process::Stdio::piped())
-                .stderr(std::process::Stdio::piped())
-                .spawn()
-                .context("failed to spawn wl-copy for text")?;
 
-            if let Some(mut stdin) = child.stdin.take() {
-                stdin
-                    .write_all(body.as_bytes())
-                    .await
-                    .context("failed to write text data to wl-copy")?;
-                drop(stdin); // Explicitly close stdin
-            }
+        tx.commit()?;
 
-            let output = child.wait_with_output().await.context("failed to wait on wl-copy")?;
-            if !output.status.success() {
-                let stderr = String::from_utf8_lossy(&output.stderr);
-                return Err(anyhow!("wl-copy failed: {}", stderr));
+        for hash in hashes {
+            if crate::retention::hash_in_use(&conn, &hash)? {
+                continue;
+            }
+            if let Err(err) = crate::retention::delete_image_files(&data_dir, &hash) {
+                warn!(hash, error=%err, "failed to delete image files");
             }
         }
-    }
 
-    Ok(())
+        Ok(DeleteResult { deleted, skipped_starred })
+    })
+    .await?
 }
 
-enum CopyPayload {
-    Image { mime: String, bytes: Vec<u8> },
-    Text { body: String },
+/// Clears `deleted_at` on `ids`, returning how many were in the trash.
+async fn restore_items(conn: &Arc<Mutex<rusqlite::Connection>>, ids: Vec<i64>) -> Result<u64> {
+    let conn = conn.clone();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let placeholders = (0..ids.len()).map(|_| "?").collect::<Vec<_>>().join(",");
+        let restored = conn.execute(
+            &format!("UPDATE items SET deleted_at = NULL WHERE deleted_at IS NOT NULL AND id IN ({placeholders})"),
+            rusqlite::params_from_iter(ids.iter()),
+        )?;
+        Ok(restored as u64)
+    })
+    .await?
 }
 
-async fn delete_items(conn: &Arc<Mutex<rusqlite::Connection>>, ids: Vec<i64>) -> Result<u64> {
+async fn trash_items(conn: &Arc<Mutex<rusqlite::Connection>>, limit: u32) -> Result<Vec<ItemSummary>> {
     let conn = conn.clone();
     tokio::task::spawn_blocking(move || {
-        let conn = conn.lock().map_err(|e| anyhow!("lock poisoned: {e}"))?;
+        let conn = conn.lock().map_err(lock_poisoned)?;
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {ITEM_COLUMNS} FROM items WHERE items.deleted_at IS NOT NULL \
+             ORDER BY items.deleted_at DESC, items.id DESC LIMIT ?"
+        ))?;
+        let rows = stmt
+            .query_map([limit], item_from_row)?
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(rows)
+    })
+    .await?
+}
 
-        let tx = conn.unchecked_transaction()?;
+/// Deletes the `images` rows of item `id`, then its original and thumbnails
+/// unless another item with the same content still has an image. Returns
+/// whether there was an image to remove.
+async fn delete_image(conn: &Arc<Mutex<rusqlite::Connection>>, data_dir: &Path, id: i64) -> Result<bool> {
+    let conn = conn.clone();
+    let data_dir = data_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
 
-        let mut hashes: Vec<String> = Vec::new();
-        {
-            let placeholders = (0..ids.len()).map(|_| "?").collect::<Vec<_>>().join(",");
-            let sql = format!(
-                "SELECT hash FROM items WHERE id IN ({}) AND starred = 0 AND hash IS NOT NULL",
-                placeholders
-            );
-            let mut stmt = tx.prepare(&sql)?;
-            let rows = stmt.query_map(
-                rusqlite::params_from_iter(ids.iter()),
-                |row| row.get::<_, String>(0),
+        let hash: Option<String> = conn
+            .query_row("SELECT hash FROM items WHERE id = ?", [id], |row| row.get(0))
+            .optional()?
+            .ok_or_else(|| anyhow::Error::new(ItemNotFound(id)))?;
+
+        let removed = conn.execute("DELETE FROM images WHERE item_id = ?", [id])? > 0;
+        if !removed {
+            return Ok(false);
+        }
+
+        if let Some(hash) = hash {
+            let shared: bool = conn.query_row(
+                "SELECT EXISTS (SELECT 1 FROM images JOIN items ON items.id = images.item_id WHERE items.hash = ?)",
+                [&hash],
+                |row| row.get(0),
             )?;
-            for r in rows {
-                hashes.push(r?);
+            if !shared {
+                if let Err(err) = crate::retention::delete_image_files(&data_dir, &hash) {
+                    warn!(id, error=%err, "failed to delete image files");
+                }
             }
         }
 
-        let placeholders = (0..ids.len()).map(|_| "?").collect::<Vec<_>>().join(",");
-        let sql_del_imgs = format!(
-            "DELETE FROM images WHERE item_id IN (SELECT id FROM items WHERE id IN ({}) AND starred = 0)",
-            placeholders
-        );
-        tx.execute(&sql_del_imgs, rusqlite::params_from_iter(ids.iter()))?;
+        Ok(true)
+    })
+    .await?
+}
 
-        let placeholders = (0..ids.len()).map(|_| "?").collect::<Vec<_>>().join(",");
-        let sql_del_items = format!(
-            "DELETE FROM items WHERE id IN ({}) AND starred = 0",
-            placeholders
-        );
-        let deleted = tx.execute(&sql_del_items, rusqlite::params_from_iter(ids.iter()))? as u64;
+/// Deletes every item created before `timestamp`, sparing starred items
+/// unless `include_starred` is set. Rows go in one transaction; image files
+/// are removed best-effort once it has committed.
+async fn delete_older_than(
+    conn: &Arc<Mutex<rusqlite::Connection>>,
+    data_dir: &Path,
+    timestamp: i64,
+    include_starred: bool,
+) -> Result<DeleteAllResult> {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .context("system time error")?
+        .as_secs() as i64;
+    if timestamp > now {
+        return Err(InvalidArgument(format!("timestamp {timestamp} is in the future")).into());
+    }
 
-        tx.commit()?;
+    let conn = conn.clone();
+    let data_dir = data_dir.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let conn = conn.lock().map_err(lock_poisoned)?;
 
-        if let Ok(data_dir) = crate::db::default_data_dir() {
-            let thumbs_dir = data_dir.join("images/thumbs");
-            for hash in hashes {
-                let p = thumbs_dir.join(format!("{hash}.png"));
-                let _ = std::fs::remove_file(&p);
-            }
+        let mut sql = String::from("SELECT id FROM items WHERE created_at < ?");
+        if !include_starred {
+            sql.push_str(" AND starred = 0");
         }
+        let ids: Vec<i64> = conn
+            .prepare(&sql)?
+            .query_map([timestamp], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
 
-        Ok(deleted)
+        delete_in_transaction(&conn, &data_dir, &ids)
     })
     .await?
 }
 
-async fn delete_all_except_starred(conn: &Arc<Mutex<rusqlite::Connection>>) -> Result<DeleteAllResult> {
+async fn delete_all_except_starred(conn: &Arc<Mutex<rusqlite::Connection>>, data_dir: &Path) -> Result<DeleteAllResult> {
     let conn = conn.clone();
+    let data_dir = data_dir.to_path_buf();
     tokio::task::spawn_blocking(move || {
-        let conn = conn.lock().map_err(|e| anyhow!("lock poisoned: {e}"))?;
+        let conn = conn.lock().map_err(lock_poisoned)?;
 
         let tx = conn.unchecked_transaction()?;
         let mut hashes: Vec<String> = Vec::new();
@@ -656,11 +2955,12 @@ async fn delete_all_except_starred(conn: &Arc<Mutex<rusqlite::Connection>>) -> R
 
         tx.commit()?;
 
-        if let Ok(data_dir) = crate::db::default_data_dir() {
-            let thumbs_dir = data_dir.join("images/thumbs");
-            for hash in hashes {
-                let p = thumbs_dir.join(format!("{hash}.png"));
-                let _ = std::fs::remove_file(&p);
+        for hash in hashes {
+            if crate::retention::hash_in_use(&conn, &hash)? {
+                continue;
+            }
+            if let Err(err) = crate::retention::delete_image_files(&data_dir, &hash) {
+                warn!(hash, error=%err, "failed to delete image files");
             }
         }
 
@@ -671,3 +2971,828 @@ async fn delete_all_except_starred(conn: &Arc<Mutex<rusqlite::Connection>>) -> R
     })
     .await?
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `lines` over one connection and returns the parsed responses.
+    async fn exchange(state: Arc<AppState>, lines: &[&str]) -> Vec<Value> {
+        let (client, server) = tokio::io::duplex(64 * 1024);
+        let server = tokio::spawn(handle_connection(server, state, None, CancellationToken::new()));
+        let (reader, mut writer) = tokio::io::split(client);
+        for line in lines {
+            writer.write_all(format!("{line}\n").as_bytes()).await.unwrap();
+        }
+        writer.shutdown().await.unwrap();
+        drop(writer);
+
+        let mut responses = Vec::new();
+        let mut reader = BufReader::new(reader).lines();
+        while let Some(line) = reader.next_line().await.unwrap() {
+            responses.push(serde_json::from_str(&line).unwrap());
+        }
+        server.await.unwrap();
+        responses
+    }
+
+    /// Inserts a bare item and returns its id.
+    fn insert_item(state: &AppState, body: &str, kind: &str, created_at: i64, starred: bool) -> i64 {
+        let conn = state.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO items (created_at, updated_at, last_used, title, body, hash, kind, starred) \
+             VALUES (?1, ?1, ?1, ?2, ?2, ?3, ?4, ?5)",
+            rusqlite::params![created_at, body, crate::clipboard::compute_hash(body.as_bytes()), kind, starred],
+        )
+        .unwrap();
+        conn.last_insert_rowid()
+    }
+
+    /// Gives item `id` an image row and an original file named by its hash;
+    /// returns the file's path.
+    fn attach_image(state: &AppState, id: i64, byte_size: i64) -> std::path::PathBuf {
+        let conn = state.conn.lock().unwrap();
+        let hash: String = conn.query_row("SELECT hash FROM items WHERE id = ?", [id], |row| row.get(0)).unwrap();
+        conn.execute(
+            "INSERT INTO images (item_id, created_at, mime, bytes, byte_size) VALUES (?, 0, 'image/png', x'00', ?)",
+            rusqlite::params![id, byte_size],
+        )
+        .unwrap();
+        let dir = state.paths.originals_dir();
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("{hash}.png"));
+        std::fs::write(&path, b"png").unwrap();
+        path
+    }
+
+    fn execute(state: &AppState, sql: &str, params: impl rusqlite::Params) {
+        state.conn.lock().unwrap().execute(sql, params).unwrap();
+    }
+
+    /// Ids of the items in a listing response, in order.
+    fn ids(response: &Value) -> Vec<i64> {
+        let items = response["data"].as_array().unwrap_or_else(|| panic!("not a listing: {response}"));
+        items.iter().map(|item| item["id"].as_i64().unwrap()).collect()
+    }
+
+    fn item_exists(state: &AppState, id: i64) -> bool {
+        let conn = state.conn.lock().unwrap();
+        conn.query_row("SELECT EXISTS (SELECT 1 FROM items WHERE id = ?)", [id], |row| row.get(0)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn clear_removes_files_of_deleted_items_only() {
+        let state = AppState::for_tests();
+        let old = insert_item(&state, "old", "image", 0, false);
+        let old_file = attach_image(&state, old, 3);
+        let starred = insert_item(&state, "starred", "image", 0, true);
+        let starred_file = attach_image(&state, starred, 3);
+        let text = insert_item(&state, "text", "text", 0, false);
+
+        let result = clear_items(&state.conn, &state.paths.data_dir, Some(1), Some("image".into()), false)
+            .await
+            .unwrap();
+        assert_eq!(result.deleted_items, 1);
+        assert_eq!(result.deleted_images, 1);
+        assert!(!item_exists(&state, old));
+        assert!(!old_file.exists());
+        assert!(item_exists(&state, starred));
+        assert!(starred_file.exists());
+        assert!(item_exists(&state, text));
+    }
+
+    #[tokio::test]
+    async fn delete_older_than_deletes_rows_and_files_before_the_cutoff() {
+        let state = AppState::for_tests();
+        let old = insert_item(&state, "old", "image", 100, false);
+        let old_file = attach_image(&state, old, 3);
+        let old_starred = insert_item(&state, "old starred", "text", 100, true);
+        let new = insert_item(&state, "new", "text", 300, false);
+
+        let result = delete_older_than(&state.conn, &state.paths.data_dir, 200, false).await.unwrap();
+        assert_eq!(result.deleted_items, 1);
+        assert_eq!(result.deleted_images, 1);
+        assert!(!item_exists(&state, old));
+        assert!(!old_file.exists());
+        assert!(item_exists(&state, old_starred));
+        assert!(item_exists(&state, new));
+    }
+
+    #[tokio::test]
+    async fn delete_older_than_rejects_future_timestamps_as_invalid() {
+        let state = AppState::for_tests();
+        let Err(err) = delete_older_than(&state.conn, &state.paths.data_dir, i64::MAX, false).await else {
+            panic!("future timestamp accepted");
+        };
+        assert_eq!(error_code(&err), IpcError::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn delete_all_except_starred_removes_originals() {
+        let state = AppState::for_tests();
+        let plain = insert_item(&state, "plain", "image", 0, false);
+        let plain_file = attach_image(&state, plain, 3);
+        let starred = insert_item(&state, "starred", "image", 0, true);
+        let starred_file = attach_image(&state, starred, 3);
+
+        let result = delete_all_except_starred(&state.conn, &state.paths.data_dir).await.unwrap();
+        assert_eq!(result.deleted_items, 1);
+        assert!(!plain_file.exists());
+        assert!(starred_file.exists());
+    }
+
+    #[tokio::test]
+    async fn reorder_distinguishes_unstarred_from_missing_items() {
+        let state = AppState::for_tests();
+        let unstarred = insert_item(&state, "plain", "text", 0, false);
+
+        let Err(err) = reorder_item(&state.conn, unstarred, 1).await else {
+            panic!("unstarred item reordered");
+        };
+        assert_eq!(error_code(&err), IpcError::InvalidRequest);
+        let Err(err) = reorder_item(&state.conn, unstarred + 1, 1).await else {
+            panic!("missing item reordered");
+        };
+        assert_eq!(error_code(&err), IpcError::NotFound);
+    }
+
+    #[tokio::test]
+    async fn missing_paste_tool_has_its_own_code() {
+        let behavior = crate::config::Behavior {
+            paste_command: Some("memoria-test-no-such-tool --flag".into()),
+            paste_delay_ms: 0,
+            ..Default::default()
+        };
+        let Err(err) = simulate_paste(&behavior).await else {
+            panic!("missing paste tool ran");
+        };
+        assert_eq!(error_code(&err), IpcError::PasteToolUnavailable);
+        assert!(err.to_string().contains("memoria-test-no-such-tool"));
+    }
+
+    #[tokio::test]
+    async fn copy_refuses_images_whose_image_was_deleted() {
+        let state = AppState::for_tests();
+        let id = insert_item(&state, "", "image", 0, false);
+        attach_image(&state, id, 3);
+        assert!(delete_image(&state.conn, &state.paths.data_dir, id).await.unwrap());
+
+        let Err(err) = copy_to_clipboard(&state, id, false, None, CopyMime::Canonical).await else {
+            panic!("copied an image item without an image");
+        };
+        assert_eq!(error_code(&err), IpcError::InvalidRequest);
+    }
+
+    #[tokio::test]
+    async fn stats_count_image_sizes_outside_the_trash() {
+        let state = AppState::for_tests();
+        let kept = insert_item(&state, "kept", "image", 0, false);
+        attach_image(&state, kept, 1000);
+        let trashed = insert_item(&state, "trashed", "image", 0, false);
+        attach_image(&state, trashed, 50);
+        state.conn.lock().unwrap().execute("UPDATE items SET deleted_at = 1 WHERE id = ?", [trashed]).unwrap();
+
+        let stats = stats(&state).await.unwrap();
+        assert_eq!(stats.image_items, 1);
+        assert_eq!(stats.image_bytes, 1000);
+        assert_eq!(stats.trashed_items, 1);
+    }
+
+    #[tokio::test]
+    async fn handshake_is_an_alias_of_hello() {
+        let responses = exchange(
+            AppState::for_tests(),
+            &[
+                r#"{"cmd": "hello", "client": "test", "protocol": 1}"#,
+                r#"{"cmd": "handshake", "client_version": 1}"#,
+            ],
+        )
+        .await;
+        assert_eq!(responses[0], responses[1]);
+        assert_eq!(responses[0]["data"]["protocol"], PROTOCOL_VERSION);
+        assert_eq!(responses[0]["data"]["daemon_version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[tokio::test]
+    async fn hello_with_a_newer_protocol_reports_the_highest_supported() {
+        let request = format!(r#"{{"cmd": "handshake", "client_version": {}}}"#, PROTOCOL_VERSION + 1);
+        let responses = exchange(AppState::for_tests(), &[&request, r#"{"cmd": "ping"}"#]).await;
+        assert_eq!(responses[0]["ok"], false);
+        assert_eq!(responses[0]["code"], "invalid_request");
+        assert_eq!(responses[0]["data"]["max_protocol"], PROTOCOL_VERSION);
+        // The connection stays usable.
+        assert_eq!(responses[1]["ok"], true);
+    }
+
+    #[tokio::test]
+    async fn handshake_advertises_the_command_set() {
+        let responses = exchange(AppState::for_tests(), &[r#"{"cmd": "handshake", "client_version": 1}"#]).await;
+        assert_eq!(responses[0]["version"], 1);
+        let advertised: Vec<&str> = responses[0]["data"]["capabilities"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|cmd| cmd.as_str().unwrap())
+            .collect();
+        assert_eq!(advertised, COMMANDS);
+        for cmd in ["list", "search", "copy", "delete", "handshake", "hello", "set_settings"] {
+            assert!(advertised.contains(&cmd), "{cmd} not advertised");
+        }
+    }
+
+    #[test]
+    fn every_advertised_command_parses() {
+        for cmd in COMMANDS {
+            let err = parse_request(&serde_json::json!({ "cmd": cmd })).err().map(|e| e.to_string());
+            assert!(
+                !err.as_deref().unwrap_or("").contains("not implemented"),
+                "{cmd} is advertised but not implemented"
+            );
+        }
+        assert!(parse_request(&serde_json::json!({ "cmd": "no_such_command" })).is_err());
+    }
+
+    #[test]
+    fn every_reported_setting_can_be_set() {
+        let state = AppState::for_tests();
+        let cfg = state.config().unwrap().clone();
+        let mut settings = settings_json(&state, &cfg).as_object().unwrap().clone();
+        // Runtime state and locations, not settings.
+        settings.remove("capture");
+        settings.remove("paths");
+
+        let merged = cfg.merged_with(&settings).unwrap();
+        assert_eq!(settings_json(&state, &merged), settings_json(&state, &cfg));
+    }
+
+    #[tokio::test]
+    async fn set_settings_saves_and_applies_the_patch() {
+        let state = AppState::for_tests();
+        let responses = exchange(
+            state.clone(),
+            &[r#"{"cmd": "set_settings", "retention": {"days": 9}, "behavior": {"trash_days": 2}}"#],
+        )
+        .await;
+        assert_eq!(responses[0]["data"]["retention"]["days"], 9);
+        assert_eq!(state.config().unwrap().behavior.trash_days, Some(2));
+        let saved = std::fs::read_to_string(&state.paths.config).unwrap();
+        let saved: Config = toml::from_str(&saved).unwrap();
+        assert_eq!(saved.retention.days, 9);
+    }
+
+    #[tokio::test]
+    async fn trash_days_set_at_runtime_survive_the_next_cleanup() {
+        let state = AppState::for_tests();
+        assert_eq!(state.config().unwrap().behavior.trash_days, None);
+        let now = crate::retention::RetentionPolicy::cutoff_timestamp(0).unwrap();
+        let id = insert_item(&state, "keep me restorable", "text", now, false);
+
+        let responses = exchange(
+            state.clone(),
+            &[
+                r#"{"cmd": "set_settings", "behavior": {"trash_days": 7}}"#,
+                &format!(r#"{{"cmd": "delete", "ids": [{id}]}}"#),
+            ],
+        )
+        .await;
+        assert_eq!(responses[1]["data"]["trashed"], true);
+        // Trashed an hour ago: well inside 7 days, but before a policy
+        // without trash_days would keep it.
+        execute(&state, "UPDATE items SET deleted_at = deleted_at - 3600 WHERE id = ?", [id]);
+
+        crate::retention::run_live_cleanup(&state).await.unwrap();
+        let responses = exchange(
+            state.clone(),
+            &[&format!(r#"{{"cmd": "restore", "ids": [{id}]}}"#), r#"{"cmd": "list"}"#],
+        )
+        .await;
+        assert_eq!(responses[0]["ok"], true, "{}", responses[0]);
+        assert_eq!(ids(&responses[1]), [id]);
+    }
+
+    #[tokio::test]
+    async fn size_sort_uses_the_recorded_image_size() {
+        let state = AppState::for_tests();
+        let file_only = insert_item(&state, "", "image", 0, false);
+        attach_image(&state, file_only, 5000);
+        execute(&state, "UPDATE images SET bytes = NULL WHERE item_id = ?", [file_only]);
+        let text = insert_item(&state, &"x".repeat(100), "text", 0, false);
+        let small = insert_item(&state, "tiny", "image", 0, false);
+        attach_image(&state, small, 10);
+
+        let responses = exchange(state, &[r#"{"cmd": "list", "sort": "size"}"#]).await;
+        assert_eq!(ids(&responses[0]), [file_only, text, small]);
+    }
+
+    #[tokio::test]
+    async fn paste_falls_back_past_a_failing_tool() {
+        run_paste_commands(&["false", "true"]).await.unwrap();
+        run_paste_commands(&["memoria-no-such-tool", "true"]).await.unwrap();
+
+        let err = run_paste_commands(&["sh -c exit_1_please", "memoria-no-such-tool"]).await.unwrap_err();
+        assert!(err.to_string().starts_with("sh failed:"), "{err}");
+        assert_eq!(error_code(&err), IpcError::Internal);
+
+        let err = run_paste_commands(&["memoria-no-such-tool", "memoria-other-tool"]).await.unwrap_err();
+        assert_eq!(error_code(&err), IpcError::PasteToolUnavailable);
+    }
+
+    #[tokio::test]
+    async fn clear_refuses_zero_days() {
+        let state = AppState::for_tests();
+        let now = crate::retention::RetentionPolicy::cutoff_timestamp(0).unwrap();
+        let id = insert_item(&state, "fresh", "text", now - 60, false);
+
+        let responses = exchange(
+            state,
+            &[
+                r#"{"cmd": "clear", "older_than_days": 0}"#,
+                r#"{"cmd": "clear", "older_than_days": 1}"#,
+                r#"{"cmd": "list"}"#,
+            ],
+        )
+        .await;
+        assert_eq!(responses[0]["ok"], false);
+        assert_eq!(responses[0]["code"], "invalid_request");
+        assert_eq!(responses[1]["ok"], true);
+        assert_eq!(ids(&responses[2]), [id]);
+    }
+
+    #[tokio::test]
+    async fn listings_report_missing_files() {
+        let state = AppState::for_tests();
+        let present = state.paths.data_dir.join("present.txt");
+        std::fs::write(&present, b"here").unwrap();
+        let gone = state.paths.data_dir.join("gone.txt");
+        let body = format!("file://{}\nfile://{}\n", present.display(), gone.display());
+        insert_item(&state, &body, "files", 0, false);
+
+        let responses = exchange(state, &[r#"{"cmd": "list", "full_body": true}"#]).await;
+        let item = &responses[0]["data"][0];
+        assert_eq!(item["files"].as_array().unwrap().len(), 2);
+        assert_eq!(item["missing_files"], serde_json::json!([gone.display().to_string()]));
+    }
+
+    #[tokio::test]
+    async fn idle_connections_close_on_shutdown() {
+        let (_client, server) = tokio::io::duplex(1024);
+        let shutdown = CancellationToken::new();
+        let handler = tokio::spawn(handle_connection(server, AppState::for_tests(), None, shutdown.clone()));
+        shutdown.cancel();
+        tokio::time::timeout(std::time::Duration::from_secs(1), handler)
+            .await
+            .expect("idle handler kept running after shutdown")
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn workspace_filter_keeps_unknown_workspaces() {
+        let state = AppState::for_tests();
+        let first = insert_item(&state, "alpha one", "text", 30, false);
+        let second = insert_item(&state, "alpha two", "text", 20, false);
+        let unknown = insert_item(&state, "alpha three", "text", 10, false);
+        execute(&state, "UPDATE items SET workspace = '1' WHERE id = ?", [first]);
+        execute(&state, "UPDATE items SET workspace = '2' WHERE id = ?", [second]);
+
+        let responses = exchange(
+            state,
+            &[
+                r#"{"cmd": "list", "workspace": "1"}"#,
+                r#"{"cmd": "list", "workspace": "2"}"#,
+                r#"{"cmd": "list"}"#,
+                r#"{"cmd": "search", "query": "alpha", "workspace": "2"}"#,
+                r#"{"cmd": "count", "workspace": "1"}"#,
+            ],
+        )
+        .await;
+        assert_eq!(ids(&responses[0]), [first, unknown]);
+        assert_eq!(ids(&responses[1]), [second, unknown]);
+        assert_eq!(ids(&responses[2]), [first, second, unknown]);
+        let mut searched = ids(&responses[3]);
+        searched.sort();
+        assert_eq!(searched, [second, unknown]);
+        assert_eq!(responses[4]["data"]["count"], 2);
+    }
+
+    #[tokio::test]
+    async fn search_snippets_highlight_the_matched_term() {
+        let state = AppState::for_tests();
+        insert_item(&state, "the build failed with a linker error", "text", 0, false);
+
+        let responses = exchange(
+            state,
+            &[
+                r#"{"cmd": "search", "query": "linker"}"#,
+                r#"{"cmd": "search", "query": "linker", "snippet_open": "[", "snippet_close": "]"}"#,
+            ],
+        )
+        .await;
+        let snippet = responses[0]["data"][0]["snippet"].as_str().unwrap();
+        assert!(snippet.contains("<b>linker</b>"), "{snippet}");
+        let snippet = responses[1]["data"][0]["snippet"].as_str().unwrap();
+        assert!(snippet.contains("[linker]"), "{snippet}");
+    }
+
+    #[tokio::test]
+    async fn reorder_moves_starred_items_and_renumbers_them() {
+        let state = AppState::for_tests();
+        let a = insert_item(&state, "a", "text", 30, true);
+        let b = insert_item(&state, "b", "text", 20, true);
+        let c = insert_item(&state, "c", "text", 10, true);
+        let plain = insert_item(&state, "plain", "text", 40, false);
+        // A tie and a gap, both closed by the renumbering.
+        execute(&state, "UPDATE items SET sort_order = 5 WHERE id IN (?, ?)", [a, b]);
+
+        let responses = exchange(
+            state.clone(),
+            &[
+                &format!(r#"{{"cmd": "reorder", "id": {c}, "position": 1}}"#),
+                &format!(r#"{{"cmd": "reorder", "id": {a}, "position": 3}}"#),
+                r#"{"cmd": "list"}"#,
+            ],
+        )
+        .await;
+        assert_eq!(responses[0]["data"]["order"], serde_json::json!([c, a, b]));
+        assert_eq!(responses[1]["data"]["order"], serde_json::json!([c, b, a]));
+        // Starred items first in their manual order, the rest by recency.
+        assert_eq!(ids(&responses[2]), [c, b, a, plain]);
+
+        let conn = state.conn.lock().unwrap();
+        let orders: Vec<i64> = conn
+            .prepare("SELECT sort_order FROM items WHERE starred = 1 ORDER BY sort_order")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(orders, [1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn time_range_bounds_are_inclusive() {
+        let state = AppState::for_tests();
+        let early = insert_item(&state, "report early", "text", 100, false);
+        let middle = insert_item(&state, "report middle", "text", 200, false);
+        let late = insert_item(&state, "report late", "text", 300, false);
+
+        let responses = exchange(
+            state,
+            &[
+                r#"{"cmd": "list", "since": 100, "until": 200}"#,
+                r#"{"cmd": "list", "since": 200}"#,
+                r#"{"cmd": "list", "until": 200}"#,
+                r#"{"cmd": "list", "since": 300, "until": 300}"#,
+                r#"{"cmd": "search", "query": "report", "since": 200, "until": 300}"#,
+                r#"{"cmd": "list", "since": 201, "until": 200}"#,
+            ],
+        )
+        .await;
+        assert_eq!(ids(&responses[0]), [middle, early]);
+        assert_eq!(ids(&responses[1]), [late, middle]);
+        assert_eq!(ids(&responses[2]), [middle, early]);
+        assert_eq!(ids(&responses[3]), [late]);
+        let mut searched = ids(&responses[4]);
+        searched.sort();
+        assert_eq!(searched, [middle, late]);
+        assert_eq!(responses[5]["code"], "invalid_request");
+    }
+
+    #[tokio::test]
+    async fn copying_an_older_item_moves_it_ahead_of_newer_ones() {
+        let state = AppState::for_tests();
+        let old = insert_item(&state, "old", "text", 10, false);
+        let middle = insert_item(&state, "middle", "text", 20, false);
+        let new = insert_item(&state, "new", "text", 30, false);
+
+        let responses = exchange(
+            state,
+            &[
+                r#"{"cmd": "list"}"#,
+                &format!(r#"{{"cmd": "copy", "id": {old}}}"#),
+                r#"{"cmd": "list"}"#,
+            ],
+        )
+        .await;
+        assert_eq!(ids(&responses[0]), [new, middle, old]);
+        assert_eq!(responses[1]["data"]["copied"], true, "{}", responses[1]);
+        assert_eq!(ids(&responses[2]), [old, new, middle]);
+    }
+
+    #[tokio::test]
+    async fn tags_are_normalized_filtered_and_removed() {
+        let state = AppState::for_tests();
+        let tagged = insert_item(&state, "tagged", "text", 20, false);
+        let other = insert_item(&state, "other", "text", 10, false);
+
+        let responses = exchange(
+            state,
+            &[
+                &format!(r#"{{"cmd": "add_tag", "id": {tagged}, "tag": "  Work "}}"#),
+                &format!(r#"{{"cmd": "add_tag", "id": {tagged}, "tag": "WORK"}}"#),
+                &format!(r#"{{"cmd": "add_tag", "id": {tagged}, "tag": "urgent"}}"#),
+                &format!(r#"{{"cmd": "add_tag", "id": {other}, "tag": "home"}}"#),
+                r#"{"cmd": "list", "tag": "Work"}"#,
+                &format!(r#"{{"cmd": "remove_tag", "id": {tagged}, "tag": "work"}}"#),
+                r#"{"cmd": "list", "tag": "work"}"#,
+                &format!(r#"{{"cmd": "get", "id": {tagged}}}"#),
+            ],
+        )
+        .await;
+        assert_eq!(responses[1]["data"]["tags"], serde_json::json!(["work"]));
+        assert_eq!(responses[2]["data"]["tags"], serde_json::json!(["urgent", "work"]));
+        assert_eq!(ids(&responses[4]), [tagged]);
+        assert_eq!(responses[4]["data"][0]["tags"], serde_json::json!(["urgent", "work"]));
+        assert_eq!(responses[5]["data"]["tags"], serde_json::json!(["urgent"]));
+        assert!(ids(&responses[6]).is_empty());
+        assert_eq!(responses[7]["data"]["tags"], serde_json::json!(["urgent"]));
+    }
+
+    #[tokio::test]
+    async fn listings_send_a_preview_unless_the_full_body_is_asked_for() {
+        let state = AppState::for_tests();
+        state.config_mut().unwrap().behavior.body_preview_chars = 3;
+        let id = insert_item(&state, "東京都渋谷区", "text", 0, false);
+
+        let responses = exchange(
+            state,
+            &[
+                r#"{"cmd": "list"}"#,
+                r#"{"cmd": "list", "full_body": true}"#,
+                &format!(r#"{{"cmd": "get", "id": {id}}}"#),
+            ],
+        )
+        .await;
+        let preview = &responses[0]["data"][0];
+        assert_eq!(preview["body_preview"], "東京都");
+        assert_eq!(preview["is_truncated"], true);
+        assert!(preview.get("body").is_none());
+        assert_eq!(responses[1]["data"][0]["body"], "東京都渋谷区");
+        assert_eq!(responses[2]["data"]["body"], "東京都渋谷区");
+    }
+
+    #[test]
+    fn fts_query_keeps_unicode_words() {
+        assert_eq!(build_fts_prefix_query("Größe Übung"), r#""größe"* "übung"*"#);
+        assert_eq!(build_fts_prefix_query("Привет мир"), r#""привет"* "мир"*"#);
+        assert_eq!(build_fts_prefix_query("東京タワー"), r#""東京タワー"*"#);
+        assert_eq!(build_fts_prefix_query("café, naïve"), r#""café"* "naïve"*"#);
+    }
+
+    #[test]
+    fn fts_query_neutralizes_operators() {
+        assert_eq!(build_fts_prefix_query("foo* bar^ (baz)"), r#""foo"* "bar"* "baz"*"#);
+        assert_eq!(build_fts_prefix_query("a AND b OR NOT c"), r#""a"* "and"* "b"* "or"* "not"* "c"*"#);
+        assert_eq!(build_fts_prefix_query("NEAR(x y, 2)"), r#""near"* "x"* "y"* "2"*"#);
+        assert_eq!(build_fts_prefix_query("title:secret"), r#""title"* "secret"*"#);
+        // `-` is kept inside words, where quoting keeps it from meaning NOT.
+        assert_eq!(build_fts_prefix_query("-foo wi-fi"), r#""-foo"* "wi-fi"*"#);
+    }
+
+    #[test]
+    fn fts_query_of_nothing_searchable_is_empty() {
+        assert_eq!(build_fts_prefix_query(""), "");
+        assert_eq!(build_fts_prefix_query("   "), "");
+        assert_eq!(build_fts_prefix_query("*\"^():"), "");
+        assert_eq!(build_fts_prefix_query("\"\""), "");
+    }
+
+    #[test]
+    fn fts_query_caps_terms() {
+        let input: Vec<String> = (0..20).map(|n| format!("w{n}")).collect();
+        let query = build_fts_prefix_query(&input.join(" "));
+        assert_eq!(query.split(' ').count(), 12);
+        assert!(query.ends_with(r#""w11"*"#));
+    }
+
+    #[test]
+    fn fts_query_mixes_phrases_and_prefix_terms() {
+        assert_eq!(
+            build_fts_prefix_query(r#"ngi "connection refused" port"#),
+            r#""ngi"* "connection refused" "port"*"#
+        );
+        assert_eq!(build_fts_prefix_query(r#""a b" "c d""#), r#""a b" "c d""#);
+    }
+
+    #[test]
+    fn fts_query_strips_punctuation_inside_phrases() {
+        assert_eq!(build_fts_prefix_query(r#""error: (code 42)!""#), r#""error code 42""#);
+        assert_eq!(build_fts_prefix_query(r#""*" tail"#), r#""tail"*"#);
+    }
+
+    #[test]
+    fn fts_query_treats_an_unmatched_quote_as_a_separator() {
+        assert_eq!(build_fts_prefix_query(r#""open phrase"#), r#""open"* "phrase"*"#);
+        assert_eq!(
+            build_fts_prefix_query(r#""one two" three "four"#),
+            r#""one two" "three"* "four"*"#
+        );
+    }
+
+    #[tokio::test]
+    async fn searches_with_operator_characters_do_not_fail() {
+        let state = AppState::for_tests();
+        insert_item(&state, "NEAR the AND gate", "text", 0, false);
+        let queries = [r#"\"unbalanced"#, "*", "-", "NEAR(", "AND", "a OR", "\"\"", ""];
+        let requests: Vec<String> = queries
+            .iter()
+            .map(|query| serde_json::json!({ "cmd": "search", "query": query }).to_string())
+            .collect();
+        let requests: Vec<&str> = requests.iter().map(String::as_str).collect();
+        for (query, response) in queries.iter().zip(exchange(state, &requests).await) {
+            assert_eq!(response["ok"], true, "{query}: {response}");
+        }
+    }
+
+    #[tokio::test]
+    async fn copy_reports_last_used_and_counts_as_usage() {
+        let state = AppState::for_tests();
+        let copied = insert_item(&state, "copied", "text", 10, false);
+        let newer = insert_item(&state, "newer", "text", 20, false);
+
+        let responses = exchange(
+            state,
+            &[
+                &format!(r#"{{"cmd": "copy", "id": {copied}}}"#),
+                &format!(r#"{{"cmd": "copy", "id": {copied}}}"#),
+                r#"{"cmd": "list"}"#,
+                &format!(r#"{{"cmd": "history", "id": {copied}}}"#),
+            ],
+        )
+        .await;
+        let last_used = responses[1]["data"]["last_used"].as_i64().unwrap();
+        assert!(last_used > 20);
+        assert_eq!(ids(&responses[2]), [copied, newer]);
+        let listed = &responses[2]["data"][0];
+        assert_eq!(listed["last_used"], last_used);
+        assert_eq!(listed["use_count"], 2);
+        assert_eq!(responses[3]["data"]["use_count"], 2);
+        assert_eq!(responses[3]["data"]["used_at"][0], last_used);
+    }
+
+    #[tokio::test]
+    async fn copy_suppresses_the_hash_the_watcher_will_compute() {
+        let body = "line one  \r\nline two\n\n";
+        for normalize in [true, false] {
+            let state = AppState::for_tests();
+            state.config_mut().unwrap().behavior.normalize_text = normalize;
+            let id = insert_item(&state, body, "text", 0, false);
+
+            let responses = exchange(state.clone(), &[&format!(r#"{{"cmd": "copy", "id": {id}}}"#)]).await;
+            assert_eq!(responses[0]["ok"], true);
+            let expected = crate::clipboard::text_hash(body.as_bytes(), normalize);
+            assert!(state.take_suppressed(&expected), "normalize = {normalize}");
+            assert!(!state.take_suppressed(&expected));
+        }
+    }
+
+    #[tokio::test]
+    async fn snippets_window_long_bodies_and_cover_title_matches() {
+        let state = AppState::for_tests();
+        let titled = insert_item(&state, "nothing relevant in here", "text", 20, false);
+        execute(&state, "UPDATE items SET title = 'Deployment checklist' WHERE id = ?", [titled]);
+        let filler = "lorem ipsum dolor sit amet ".repeat(40);
+        let long = insert_item(&state, &format!("{filler}kubernetes {filler}"), "text", 10, false);
+
+        let responses = exchange(
+            state,
+            &[
+                r#"{"cmd": "search", "query": "deployment", "snippet_open": "\u0001", "snippet_close": "\u0002"}"#,
+                r#"{"cmd": "search", "query": "kubernetes"}"#,
+            ],
+        )
+        .await;
+        assert_eq!(ids(&responses[0]), [titled]);
+        let snippet = responses[0]["data"][0]["snippet"].as_str().unwrap();
+        assert!(snippet.contains("\u{1}Deployment\u{2}"), "{snippet:?}");
+
+        assert_eq!(ids(&responses[1]), [long]);
+        let snippet = responses[1]["data"][0]["snippet"].as_str().unwrap();
+        assert!(snippet.contains("<b>kubernetes</b>"), "{snippet}");
+        assert!(snippet.starts_with('…') && snippet.ends_with('…'), "{snippet}");
+        assert!(snippet.split_whitespace().count() <= 12, "{snippet}");
+    }
+
+    #[tokio::test]
+    async fn search_falls_back_to_fuzzy_matches_unless_disabled() {
+        let state = AppState::for_tests();
+        let id = insert_item(&state, "notes on kubernetes networking", "text", 0, false);
+        insert_item(&state, "grocery list", "text", 0, false);
+
+        let responses = exchange(
+            state,
+            &[
+                r#"{"cmd": "search", "query": "kubernets"}"#,
+                r#"{"cmd": "search", "query": "kubernets", "fuzzy": false}"#,
+                r#"{"cmd": "search", "query": "kubernetes"}"#,
+            ],
+        )
+        .await;
+        assert_eq!(ids(&responses[0]), [id]);
+        assert_eq!(responses[0]["data"][0]["fuzzy"], true);
+        assert!(ids(&responses[1]).is_empty());
+        assert_eq!(ids(&responses[2]), [id]);
+        assert!(responses[2]["data"][0].get("fuzzy").is_none());
+    }
+
+    #[tokio::test]
+    async fn phrase_search_matches_only_adjacent_words() {
+        let state = AppState::for_tests();
+        let adjacent = insert_item(&state, "upstream connection refused by peer", "text", 0, false);
+        insert_item(&state, "connection to db was refused", "text", 0, false);
+
+        let responses = exchange(
+            state,
+            &[
+                r#"{"cmd": "search", "query": "\"connection refused\"", "fuzzy": false}"#,
+                r#"{"cmd": "search", "query": "connection refused", "fuzzy": false}"#,
+            ],
+        )
+        .await;
+        assert_eq!(ids(&responses[0]), [adjacent]);
+        assert_eq!(ids(&responses[1]).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn search_finds_non_ascii_text() {
+        let state = AppState::for_tests();
+        let cyrillic = insert_item(&state, "Привет мир, как дела", "text", 0, false);
+        let cjk = insert_item(&state, "東京タワー 夜景", "text", 0, false);
+        let accented = insert_item(&state, "Crème brûlée recipe", "text", 0, false);
+        insert_item(&state, "plain ascii notes", "text", 0, false);
+
+        let responses = exchange(
+            state,
+            &[
+                r#"{"cmd": "search", "query": "привет", "fuzzy": false}"#,
+                r#"{"cmd": "search", "query": "ПРИВ", "fuzzy": false}"#,
+                r#"{"cmd": "search", "query": "東京タワー", "fuzzy": false}"#,
+                r#"{"cmd": "search", "query": "brûlée", "fuzzy": false}"#,
+                r#"{"cmd": "search", "query": "CRÈME", "fuzzy": false}"#,
+            ],
+        )
+        .await;
+        assert_eq!(ids(&responses[0]), [cyrillic]);
+        assert_eq!(ids(&responses[1]), [cyrillic]);
+        assert_eq!(ids(&responses[2]), [cjk]);
+        assert_eq!(ids(&responses[3]), [accented]);
+        assert_eq!(ids(&responses[4]), [accented]);
+    }
+
+    #[tokio::test]
+    async fn set_kind_only_retypes_text_items() {
+        let state = AppState::for_tests();
+        let text = insert_item(&state, "let x = 1;", "text", 0, false);
+        let image = insert_item(&state, "", "image", 0, false);
+        let files = insert_item(&state, "file:///tmp/a", "files", 0, false);
+
+        let responses = exchange(
+            state,
+            &[
+                &format!(r#"{{"cmd": "set_kind", "id": {text}, "kind": "code", "language": " Rust "}}"#),
+                &format!(r#"{{"cmd": "get", "id": {text}}}"#),
+                &format!(r#"{{"cmd": "set_kind", "id": {text}, "kind": "text", "language": "rust"}}"#),
+                &format!(r#"{{"cmd": "set_kind", "id": {text}, "kind": "image"}}"#),
+                &format!(r#"{{"cmd": "set_kind", "id": {text}, "kind": "poem"}}"#),
+                &format!(r#"{{"cmd": "set_kind", "id": {image}, "kind": "text"}}"#),
+                &format!(r#"{{"cmd": "set_kind", "id": {files}, "kind": "text"}}"#),
+                r#"{"cmd": "set_kind", "id": 999, "kind": "text"}"#,
+            ],
+        )
+        .await;
+        assert_eq!(responses[0]["data"], serde_json::json!({ "kind": "code", "language": "rust" }));
+        assert_eq!(responses[1]["data"]["kind"], "code");
+        assert_eq!(responses[1]["data"]["language"], "rust");
+        for response in &responses[2..7] {
+            assert_eq!(response["ok"], false, "{response}");
+            assert_eq!(response["code"], "invalid_request", "{response}");
+        }
+        assert_eq!(responses[7]["code"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn request_id_is_echoed_on_malformed_requests() {
+        let responses = exchange(
+            AppState::for_tests(),
+            &[
+                r#"{"request_id": 7, "cmd": "no_such_command"}"#,
+                r#"{"request_id": "b", "cmd": "get"}"#,
+                r#"{"request_id": 9, "cmd": "ping"}"#,
+            ],
+        )
+        .await;
+        assert_eq!(responses.len(), 3);
+        assert_eq!(responses[0]["ok"], false);
+        assert_eq!(responses[0]["request_id"], 7);
+        assert_eq!(responses[1]["ok"], false);
+        assert_eq!(responses[1]["request_id"], "b");
+        assert_eq!(responses[2]["ok"], true);
+        assert_eq!(responses[2]["request_id"], 9);
+    }
+
+    #[tokio::test]
+    async fn item_id_argument_is_not_echoed() {
+        let responses = exchange(AppState::for_tests(), &[r#"{"cmd": "get", "id": 42}"#]).await;
+        assert_eq!(responses[0]["code"], "not_found");
+        assert!(responses[0].get("request_id").is_none());
+    }
+}
@@ -1,56 +1,74 @@
+mod backend;
 mod config;
 mod db;
 mod clipboard;
+mod code;
 mod retention;
 mod ipc;
+mod dump;
+mod error;
+mod focus;
+mod state;
+mod fuzzy;
+mod transform;
+mod wayland;
 
 use anyhow::{Context, Result};
+use clap::Parser;
 use std::path::PathBuf;
-use tokio::net::UnixListener;
+use tokio::net::{TcpListener, TcpStream, UnixListener};
 use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
 use tracing::{info, warn};
 
+/// Clipboard history daemon for Wayland.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Config file to use instead of ~/.config/memoria/config.toml.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+    /// Directory for the database and images instead of ~/.local/share/memoria.
+    #[arg(long, value_name = "PATH")]
+    data_dir: Option<PathBuf>,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
+    let args = Args::parse();
     init_tracing();
 
-    let cfg_path = config::default_config_path()
-        .context("FAILED TO RESOLVE CONFIG PATH")?;
+    let paths = state::Paths::resolve(args.config, args.data_dir)
+        .context("FAILED TO RESOLVE PATHS")?;
     
-    let cfg = match config::load_or_default(&cfg_path) {
+    let cfg = match config::load_or_default(&paths.config) {
         Ok(cfg) => cfg,
         Err(err) => {
             eprintln!("\n❌ CONFIGURATION ERROR\n");
-            eprintln!("Failed to load config from: {}", cfg_path.display());
+            eprintln!("Failed to load config from: {}", paths.config.display());
             eprintln!("Error: {}\n", err);
             eprintln!("Please check your config file syntax or delete it to regenerate defaults.\n");
             std::process::exit(1);
         }
     };
     
-    info!(path=%cfg_path.display(), "config loaded");
+    info!(path=%paths.config.display(), "config loaded");
     info!(retention_days=cfg.retention.days, delete_unstarred_only=cfg.retention.delete_unstarred_only, "retention policy");
     info!(dedupe=cfg.behavior.dedupe, "behavior settings");
 
-    let data_dir = db::default_data_dir()
-        .context("FAILED TO RESOLVE DATA DIRECTORY")?;
-    
-    if let Err(err) = db::ensure_data_dir(&data_dir) {
+    if let Err(err) = db::ensure_data_dir(&paths.data_dir) {
         eprintln!("\n❌ DATA DIRECTORY ERROR\n");
-        eprintln!("Failed to create data directory: {}", data_dir.display());
+        eprintln!("Failed to create data directory: {}", paths.data_dir.display());
         eprintln!("Error: {}\n", err);
         eprintln!("Check file permissions and disk space.\n");
         std::process::exit(1);
     }
 
-    let db_path = db::default_db_path()
-        .context("FAILED TO RESOLVE DATABASE PATH")?;
-    
-    let conn = match db::open_and_init(&db_path) {
+    let conn = match db::open_and_init(&paths.db, &cfg.storage) {
         Ok(conn) => conn,
         Err(err) => {
             eprintln!("\n❌ DATABASE ERROR\n");
-            eprintln!("Failed to initialize database: {}", db_path.display());
+            eprintln!("Failed to initialize database: {}", paths.db.display());
             eprintln!("Error: {}\n", err);
             eprintln!("The database file may be corrupted. Try deleting it to start fresh.\n");
             std::process::exit(1);
@@ -58,28 +76,34 @@ async fn main() -> Result<()> {
     };
     
     let conn = std::sync::Arc::new(std::sync::Mutex::new(conn));
-    info!(db=%db_path.display(), "database ready");
+    info!(db=%paths.db.display(), "database ready");
+
+    if cfg.behavior.gc_on_start {
+        let conn = conn.clone();
+        let data_dir = paths.data_dir.clone();
+        tokio::task::spawn_blocking(move || {
+            let result = conn
+                .lock()
+                .map_err(|e| anyhow::anyhow!("lock poisoned: {e}"))
+                .and_then(|conn| retention::sweep_orphan_files(&conn, &data_dir));
+            match result {
+                Ok(reclaimed) => info!(reclaimed, "startup orphan sweep completed"),
+                Err(err) => warn!(error=%err, "startup orphan sweep failed"),
+            }
+        });
+    }
+
+    let network = cfg.network.clone();
+    let sock_path = paths.socket.clone();
+
+    let state = std::sync::Arc::new(state::AppState::new(conn.clone(), cfg, paths));
 
-    let cfg_for_clipboard = cfg.clone();
-    clipboard::start_watcher(conn.clone(), cfg_for_clipboard).await;
+    clipboard::start_watcher(state.clone()).await;
     info!("clipboard watcher started");
 
-    let retention_policy = retention::RetentionPolicy::from_config(&cfg);
-    retention::start_cleanup_scheduler(conn.clone(), retention_policy).await;
+    retention::start_cleanup_scheduler(state.clone()).await;
     info!("retention scheduler started");
 
-    let cfg_arc = std::sync::Arc::new(cfg);
-
-    let sock_path = match runtime_socket_path() {
-        Ok(path) => path,
-        Err(err) => {
-            eprintln!("\n❌ SOCKET PATH ERROR\n");
-            eprintln!("Failed to determine runtime socket path.");
-            eprintln!("Error: {}\n", err);
-            eprintln!("Ensure XDG_RUNTIME_DIR is set or /run/user/$UID exists.\n");
-            std::process::exit(1);
-        }
-    };
     
     let listener = match bind_unix_socket(&sock_path) {
         Ok(listener) => listener,
@@ -93,8 +117,23 @@ async fn main() -> Result<()> {
     };
     
     info!(socket=%sock_path.display(), "listening");
+    notify_systemd();
 
-    run_server(listener, sock_path, conn.clone(), cfg_arc).await
+    let mut tcp = None;
+    if let Some(addr) = network.tcp_listen {
+        match network.token.filter(|t| !t.is_empty()) {
+            None => warn!(addr=%addr, "network.tcp_listen is set without network.token; TCP listener disabled"),
+            Some(token) => match TcpListener::bind(&addr).await {
+                Ok(listener) => {
+                    warn!(addr=%addr, "listening on TCP; traffic is unencrypted");
+                    tcp = Some(TcpServer { listener, token });
+                }
+                Err(err) => warn!(addr=%addr, error=%err, "failed to bind TCP listener"),
+            },
+        }
+    }
+
+    run_server(listener, tcp, sock_path, state).await
 }
 
 fn init_tracing() {
@@ -109,13 +148,28 @@ fn init_tracing() {
         .init();
 }
 
-fn runtime_socket_path() -> Result<PathBuf> {
-    if let Ok(dir) = std::env::var("XDG_RUNTIME_DIR") {
-        return Ok(PathBuf::from(dir).join("memoria.sock"));
+/// Tells systemd the socket is ready (for `Type=notify` units) and, when
+/// `WatchdogSec` is set, keeps the watchdog fed at half its interval. A no-op
+/// outside systemd.
+fn notify_systemd() {
+    if let Err(err) = sd_notify::notify(false, &[sd_notify::NotifyState::Ready]) {
+        warn!(error=%err, "failed to notify systemd of readiness");
     }
 
-    let uid = unsafe { libc::geteuid() };
-    Ok(PathBuf::from(format!("/run/user/{uid}/memoria.sock")))
+    let mut usec = 0;
+    if sd_notify::watchdog_enabled(false, &mut usec) {
+        let interval = std::time::Duration::from_micros(usec / 2);
+        info!(interval_ms = interval.as_millis() as u64, "systemd watchdog enabled");
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                if let Err(err) = sd_notify::notify(false, &[sd_notify::NotifyState::Watchdog]) {
+                    warn!(error=%err, "failed to ping systemd watchdog");
+                }
+            }
+        });
+    }
 }
 
 fn bind_unix_socket(sock_path: &PathBuf) -> Result<UnixListener> {
@@ -131,8 +185,34 @@ fn bind_unix_socket(sock_path: &PathBuf) -> Result<UnixListener> {
     Ok(listener)
 }
 
-async fn run_server(listener: UnixListener, sock_path: PathBuf, conn: std::sync::Arc<std::sync::Mutex<rusqlite::Connection>>, cfg: std::sync::Arc<config::Config>) -> Result<()> {
+/// Listener for remote clients; each must present `token` before sending requests.
+struct TcpServer {
+    listener: TcpListener,
+    token: String,
+}
+
+/// Next remote client, or never when TCP is disabled.
+async fn accept_tcp(tcp: Option<&TcpServer>) -> std::io::Result<(TcpStream, std::net::SocketAddr)> {
+    match tcp {
+        Some(tcp) => tcp.listener.accept().await,
+        None => std::future::pending().await,
+    }
+}
+
+/// How long in-flight requests get to finish once SIGTERM or SIGINT arrives.
+/// Idle connections close as soon as shutdown starts.
+const SHUTDOWN_GRACE: std::time::Duration = std::time::Duration::from_secs(5);
+
+async fn run_server(
+    listener: UnixListener,
+    tcp: Option<TcpServer>,
+    sock_path: PathBuf,
+    state: std::sync::Arc<state::AppState>,
+) -> Result<()> {
     let mut sigterm = signal(SignalKind::terminate()).context("failed to register SIGTERM handler")?;
+    let mut sigint = signal(SignalKind::interrupt()).context("failed to register SIGINT handler")?;
+    let mut connections = tokio::task::JoinSet::new();
+    let shutdown = CancellationToken::new();
 
     loop {
         tokio::select! {
@@ -141,15 +221,19 @@ async fn run_server(listener: UnixListener, sock_path: PathBuf, conn: std::sync:
                 break;
             }
 
+            _ = sigint.recv() => {
+                info!("received SIGINT, shutting down");
+                break;
+            }
+
+            // Reap finished handlers so the set only holds live connections.
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+
             accept_res = listener.accept() => {
                 match accept_res {
                     Ok((stream, addr)) => {
                         info!(peer=?addr, "accepted connection");
-                        let conn_clone = conn.clone();
-                        let cfg_clone = cfg.clone();
-                        tokio::spawn(async move {
-                            ipc::handle_connection(stream, conn_clone, cfg_clone).await;
-                        });
+                        connections.spawn(ipc::handle_connection(stream, state.clone(), None, shutdown.clone()));
                     }
                     Err(err) => {
                         warn!(error=%err, "accept failed");
@@ -157,6 +241,43 @@ async fn run_server(listener: UnixListener, sock_path: PathBuf, conn: std::sync:
                     }
                 }
             }
+
+            accept_res = accept_tcp(tcp.as_ref()) => {
+                match accept_res {
+                    Ok((stream, addr)) => {
+                        info!(peer=%addr, "accepted TCP connection");
+                        let token = tcp.as_ref().map(|tcp| tcp.token.clone());
+                        connections.spawn(ipc::handle_connection(stream, state.clone(), token, shutdown.clone()));
+                    }
+                    Err(err) => {
+                        warn!(error=%err, "TCP accept failed");
+                        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+                    }
+                }
+            }
+        }
+    }
+
+    // Stop accepting and tell handlers to close once their current response
+    // is written.
+    drop(listener);
+    drop(tcp);
+    shutdown.cancel();
+    let open = connections.len();
+    let drained = tokio::time::timeout(SHUTDOWN_GRACE, async {
+        let mut drained = 0;
+        while connections.join_next().await.is_some() {
+            drained += 1;
+        }
+        drained
+    })
+    .await;
+    match drained {
+        Ok(drained) => info!(drained, "connections drained"),
+        Err(_) => {
+            let dropped = connections.len();
+            connections.shutdown().await;
+            warn!(drained = open - dropped, dropped, "dropped connections still open after shutdown grace period");
         }
     }
 
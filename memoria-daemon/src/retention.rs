@@ -1,97 +1,178 @@
 use anyhow::{Context, Result};
-use std::sync::Mutex;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use tracing::{info, warn};
+use rusqlite::types::Value as SqlValue;
 use rusqlite::OptionalExtension;
 
 use crate::config::Config;
-use crate::db;
+use crate::state::AppState;
 
 #[derive(Debug, Clone)]
 pub struct RetentionPolicy {
     pub days: u32,
+    /// Per-kind overrides of `days`; kinds not listed use `days`.
+    pub per_kind: BTreeMap<String, u32>,
     pub delete_unstarred_only: bool,
+    /// Time between scheduled cleanups.
+    pub interval: Duration,
+    /// Days trashed items are kept before being purged; `None` when the
+    /// trash is disabled.
+    pub trash_days: Option<u32>,
 }
 
 impl RetentionPolicy {
     pub fn from_config(cfg: &Config) -> Self {
         Self {
             days: cfg.retention.days,
+            per_kind: cfg.retention.per_kind.clone(),
             delete_unstarred_only: cfg.retention.delete_unstarred_only,
+            interval: Duration::from_secs(cfg.retention.interval_secs.unwrap_or(86400)),
+            trash_days: cfg.behavior.trash_days,
         }
     }
 
-    pub fn cutoff_timestamp(&self) -> Result<i64> {
+    /// Items created before this are expired when kept for `days`.
+    pub fn cutoff_timestamp(days: u32) -> Result<i64> {
         let now = SystemTime::now()
             .duration_since(UNIX_EPOCH)
             .context("system time error")?
             .as_secs() as i64;
 
-        let retention_seconds = (self.days as i64) * 86400;
+        let retention_seconds = (days as i64) * 86400;
         Ok(now - retention_seconds)
     }
+
+    /// Items trashed before this are purged. With the trash disabled that is
+    /// now, so anything trashed while it was enabled goes on the next run.
+    pub fn trash_cutoff_timestamp(&self) -> Result<i64> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("system time error")?
+            .as_secs() as i64;
+
+        Ok(now - self.trash_days.unwrap_or(0) as i64 * 86400)
+    }
 }
 
+/// Expires old items in one pass per kind in `per_kind`, then one at `days`
+/// for every other kind, so a kind's own setting always wins.
 pub async fn run_cleanup(
-    conn: std::sync::Arc<Mutex<rusqlite::Connection>>,
+    conn: Arc<Mutex<rusqlite::Connection>>,
+    data_dir: &Path,
     policy: RetentionPolicy,
 ) -> Result<()> {
-    let cutoff = policy.cutoff_timestamp()?;
-
     let conn_guard = conn.lock().map_err(|e| anyhow::anyhow!("lock poisoned: {}", e))?;
 
-    let query = if policy.delete_unstarred_only {
-        "SELECT id FROM items WHERE created_at < ? AND starred = 0"
-    } else {
-        "SELECT id FROM items WHERE created_at < ?"
-    };
+    purge_trash(&conn_guard, data_dir, policy.trash_cutoff_timestamp()?)?;
+
+    let mut deleted_count = 0;
+    for (kind, days) in &policy.per_kind {
+        let cutoff = RetentionPolicy::cutoff_timestamp(*days)?;
+        let deleted = expire_items(&conn_guard, data_dir, &policy, cutoff, " AND kind = ?", vec![SqlValue::Text(kind.clone())])?;
+        if deleted > 0 {
+            info!(kind = kind.as_str(), deleted, retention_days = days, "expired items of kind");
+        }
+        deleted_count += deleted;
+    }
+
+    // Items from before kinds were recorded have none and fall under `days`.
+    let placeholders = vec!["?"; policy.per_kind.len()].join(",");
+    let others = format!(" AND (kind IS NULL OR kind NOT IN ({placeholders}))");
+    let kinds = policy.per_kind.keys().map(|kind| SqlValue::Text(kind.clone())).collect();
+    deleted_count += expire_items(&conn_guard, data_dir, &policy, RetentionPolicy::cutoff_timestamp(policy.days)?, &others, kinds)?;
+
+    if deleted_count == 0 {
+        info!("cleanup: no items to delete");
+        return Ok(());
+    }
+
+    info!(
+        deleted_count,
+        retention_days = policy.days,
+        delete_unstarred_only = policy.delete_unstarred_only,
+        "cleanup run completed"
+    );
+
+    Ok(())
+}
 
-    let mut stmt = conn_guard
-        .prepare(query)
+/// Deletes items created before `cutoff` that also match `clause`, an
+/// ` AND ...` condition bound to `params`. Returns how many were deleted.
+fn expire_items(
+    conn: &rusqlite::Connection,
+    data_dir: &Path,
+    policy: &RetentionPolicy,
+    cutoff: i64,
+    clause: &str,
+    params: Vec<SqlValue>,
+) -> Result<usize> {
+    let mut query = String::from("SELECT id FROM items WHERE created_at < ?");
+    if policy.delete_unstarred_only {
+        query.push_str(" AND starred = 0");
+    }
+    query.push_str(clause);
+
+    let mut stmt = conn
+        .prepare(&query)
         .context("failed to prepare deletion query")?;
 
+    let params = std::iter::once(SqlValue::Integer(cutoff)).chain(params);
     let item_ids: Vec<i64> = stmt
-        .query_map([cutoff], |row| row.get(0))
+        .query_map(rusqlite::params_from_iter(params), |row| row.get(0))
         .context("failed to query items for deletion")?
         .collect::<std::result::Result<Vec<i64>, _>>()
         .context("failed to collect item IDs")?;
 
-    if item_ids.is_empty() {
-        info!("cleanup: no items to delete");
-        return Ok(());
+    for item_id in &item_ids {
+        if let Err(err) = delete_item_and_files(conn, data_dir, *item_id) {
+            warn!(item_id, error=%err, "failed to delete item");
+        }
     }
 
+    Ok(item_ids.len())
+}
+
+/// Permanently deletes items trashed before `cutoff`, starred or not.
+fn purge_trash(conn: &rusqlite::Connection, data_dir: &Path, cutoff: i64) -> Result<()> {
+    let item_ids: Vec<i64> = conn
+        .prepare("SELECT id FROM items WHERE deleted_at < ?")
+        .context("failed to prepare trash query")?
+        .query_map([cutoff], |row| row.get(0))
+        .context("failed to query trashed items")?
+        .collect::<std::result::Result<Vec<i64>, _>>()
+        .context("failed to collect trashed item IDs")?;
+
     for item_id in &item_ids {
-        if let Err(err) = delete_item_and_files(&conn_guard, *item_id) {
-            warn!(item_id, error=%err, "failed to delete item");
+        if let Err(err) = delete_item_and_files(conn, data_dir, *item_id) {
+            warn!(item_id, error=%err, "failed to purge trashed item");
         }
     }
 
-    let deleted_count = item_ids.len();
-    info!(
-        deleted_count,
-        retention_days = policy.days,
-        delete_unstarred_only = policy.delete_unstarred_only,
-        "cleanup run completed"
-    );
+    if !item_ids.is_empty() {
+        info!(purged = item_ids.len(), "emptied expired trash");
+    }
 
     Ok(())
 }
 
 pub fn delete_item_and_files(
     conn: &rusqlite::Connection,
+    data_dir: &Path,
     item_id: i64,
 ) -> Result<()> {
-    let mut stmt = conn
-        .prepare("SELECT id FROM images WHERE item_id = ?")
-        .context("failed to prepare image query")?;
-
-    let _image_ids: Vec<i64> = stmt
-        .query_map([item_id], |row| row.get(0))
-        .context("failed to query images")?
-        .collect::<std::result::Result<Vec<i64>, _>>()
-        .context("failed to collect image IDs")?;
+    if let Some(hash) = delete_item(conn, item_id)? {
+        delete_image_files(data_dir, &hash)?;
+    }
+    Ok(())
+}
 
+/// Deletes an item's row but leaves its files, for callers inside a
+/// transaction. Returns the item's hash when no other item still has it;
+/// its files should go once the deletion is committed.
+pub fn delete_item(conn: &rusqlite::Connection, item_id: i64) -> Result<Option<String>> {
     let hash: Option<String> = conn
         .query_row(
             "SELECT hash FROM items WHERE id = ?",
@@ -99,20 +180,27 @@ pub fn delete_item_and_files(
             |row| row.get(0),
         )
         .optional()
-        .context("failed to query item hash")?;
+        .context("failed to query item hash")?
+        .flatten();
 
     conn.execute("DELETE FROM items WHERE id = ?", [item_id])
         .context("failed to delete item")?;
 
-    if let Some(hash) = hash {
-        delete_image_files(&hash)?;
+    match hash {
+        Some(hash) if !hash_in_use(conn, &hash)? => Ok(Some(hash)),
+        _ => Ok(None),
     }
+}
 
-    Ok(())
+/// Whether any item still has `hash`. Items with equal content share their
+/// image files, so those must outlive every such item.
+pub fn hash_in_use(conn: &rusqlite::Connection, hash: &str) -> Result<bool> {
+    conn.query_row("SELECT EXISTS (SELECT 1 FROM items WHERE hash = ?)", [hash], |row| row.get(0))
+        .context("failed to query items by hash")
 }
-fn delete_image_files(hash: &str) -> Result<()> {
-    let data_dir = db::default_data_dir()?;
 
+/// Removes the original and thumbnails stored for `hash` under `data_dir`.
+pub fn delete_image_files(data_dir: &Path, hash: &str) -> Result<()> {
     let originals_dir = data_dir.join("images/originals");
     if originals_dir.exists() {
         if let Ok(entries) = std::fs::read_dir(&originals_dir) {
@@ -139,39 +227,180 @@ fn delete_image_files(hash: &str) -> Result<()> {
         }
     }
 
-    let thumbnail_path = data_dir.join(format!("images/thumbs/{}.png", hash));
-    if let Err(err) = std::fs::remove_file(&thumbnail_path) {
-        if err.kind() != std::io::ErrorKind::NotFound {
-            warn!(
-                path=%thumbnail_path.display(),
-                error=%err,
-                "failed to delete thumbnail"
-            );
+    crate::clipboard::remove_thumbnails(&data_dir.join("images/thumbs"), hash);
+
+    Ok(())
+}
+
+/// Deletes files under `images/originals` and `images/thumbs` whose hash no
+/// longer belongs to any item, returning how many were removed.
+pub fn sweep_orphan_files(conn: &rusqlite::Connection, data_dir: &Path) -> Result<u64> {
+    let mut stmt = conn
+        .prepare("SELECT hash FROM items WHERE hash IS NOT NULL")
+        .context("failed to prepare hash query")?;
+    let hashes: HashSet<String> = stmt
+        .query_map([], |row| row.get(0))
+        .context("failed to query item hashes")?
+        .collect::<std::result::Result<_, _>>()
+        .context("failed to collect item hashes")?;
+
+    let mut removed: u64 = 0;
+    for dir in ["images/originals", "images/thumbs"] {
+        let Ok(entries) = std::fs::read_dir(data_dir.join(dir)) else {
+            continue;
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if !path.is_file() {
+                continue;
+            }
+            let Some(hash) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+            if hashes.contains(hash) {
+                continue;
+            }
+
+            match std::fs::remove_file(&path) {
+                Ok(()) => removed += 1,
+                Err(err) => warn!(path=%path.display(), error=%err, "failed to delete orphaned file"),
+            }
         }
     }
 
-    Ok(())
+    if removed > 0 {
+        info!(removed, "removed orphaned image files");
+    }
+
+    Ok(removed)
 }
 
-pub async fn start_cleanup_scheduler(
-    conn: std::sync::Arc<Mutex<rusqlite::Connection>>,
-    policy: RetentionPolicy,
-) {
+/// Runs `run_cleanup` with the policy the live config gives now, so
+/// retention changes made through `set_settings` apply from the next sweep.
+pub async fn run_live_cleanup(state: &AppState) -> Result<()> {
+    let policy = RetentionPolicy::from_config(&*state.config()?);
+    run_cleanup(state.conn.clone(), &state.paths.data_dir, policy).await
+}
+
+pub async fn start_cleanup_scheduler(state: Arc<AppState>) {
     tokio::spawn(async move {
         info!("running initial cleanup");
-        if let Err(err) = run_cleanup(conn.clone(), policy.clone()).await {
+        if let Err(err) = run_live_cleanup(&state).await {
             warn!(error=%err, "initial cleanup failed");
         }
 
-        let mut interval = tokio::time::interval(std::time::Duration::from_secs(86400));
-        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Skip);
-
+        // The wait is re-read every round so a changed `interval_secs` applies
+        // after the current one. Sleeping after each sweep means one that
+        // overruns (or a suspended laptop) never fires runs back to back.
         loop {
-            interval.tick().await;
+            let period = state
+                .config()
+                .map(|cfg| RetentionPolicy::from_config(&cfg).interval)
+                .unwrap_or(Duration::from_secs(86400));
+            tokio::time::sleep(period).await;
             info!("running scheduled cleanup");
-            if let Err(err) = run_cleanup(conn.clone(), policy.clone()).await {
+            if let Err(err) = run_live_cleanup(&state).await {
                 warn!(error=%err, "scheduled cleanup failed");
             }
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86400;
+
+    fn open() -> Arc<Mutex<rusqlite::Connection>> {
+        let cfg = Config::default();
+        let conn = crate::db::open_and_init(Path::new(":memory:"), &cfg.storage).unwrap();
+        Arc::new(Mutex::new(conn))
+    }
+
+    fn policy(days: u32, per_kind: &[(&str, u32)]) -> RetentionPolicy {
+        RetentionPolicy {
+            days,
+            per_kind: per_kind.iter().map(|(kind, days)| (kind.to_string(), *days)).collect(),
+            delete_unstarred_only: true,
+            interval: Duration::from_secs(86400),
+            trash_days: None,
+        }
+    }
+
+    /// Inserts an item of `kind` created `age_days` ago.
+    fn insert(conn: &Mutex<rusqlite::Connection>, kind: &str, age_days: i64, starred: bool) -> i64 {
+        let created_at = RetentionPolicy::cutoff_timestamp(0).unwrap() - age_days * DAY;
+        let conn = conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO items (created_at, updated_at, title, body, hash, kind, starred) VALUES (?1, ?1, ?2, ?2, ?3, ?4, ?5)",
+            rusqlite::params![created_at, format!("{kind} {age_days}"), format!("{kind}-{age_days}-{starred}"), kind, starred],
+        )
+        .unwrap();
+        conn.last_insert_rowid()
+    }
+
+    fn remaining(conn: &Mutex<rusqlite::Connection>) -> Vec<i64> {
+        let conn = conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT id FROM items ORDER BY id").unwrap();
+        stmt.query_map([], |row| row.get(0)).unwrap().collect::<rusqlite::Result<_>>().unwrap()
+    }
+
+    #[tokio::test]
+    async fn images_expire_before_text() {
+        let conn = open();
+        let data_dir = std::env::temp_dir();
+        let new_image = insert(&conn, "image", 3, false);
+        insert(&conn, "image", 10, false);
+        let old_text = insert(&conn, "text", 10, false);
+        let new_url = insert(&conn, "url", 60, false);
+        insert(&conn, "text", 100, false);
+
+        run_cleanup(conn.clone(), &data_dir, policy(90, &[("image", 7)])).await.unwrap();
+        assert_eq!(remaining(&conn), [new_image, old_text, new_url]);
+    }
+
+    #[tokio::test]
+    async fn a_kind_can_be_kept_longer_than_days() {
+        let conn = open();
+        let data_dir = std::env::temp_dir();
+        let code = insert(&conn, "code", 200, false);
+        insert(&conn, "text", 200, false);
+
+        run_cleanup(conn.clone(), &data_dir, policy(30, &[("code", 365)])).await.unwrap();
+        assert_eq!(remaining(&conn), [code]);
+    }
+
+    #[tokio::test]
+    async fn items_without_a_kind_fall_under_days() {
+        let conn = open();
+        let data_dir = std::env::temp_dir();
+        let kept = insert(&conn, "text", 5, false);
+        insert(&conn, "text", 50, false);
+        conn.lock().unwrap().execute("UPDATE items SET kind = NULL", []).unwrap();
+
+        run_cleanup(conn.clone(), &data_dir, policy(30, &[("text", 365)])).await.unwrap();
+        assert_eq!(remaining(&conn), [kept]);
+
+        insert(&conn, "text", 50, false);
+        run_cleanup(conn.clone(), &data_dir, policy(30, &[])).await.unwrap();
+        assert_eq!(remaining(&conn), [kept]);
+    }
+
+    #[tokio::test]
+    async fn starred_items_survive_when_only_unstarred_expire() {
+        let conn = open();
+        let data_dir = std::env::temp_dir();
+        let starred = insert(&conn, "image", 30, true);
+        insert(&conn, "image", 30, false);
+
+        run_cleanup(conn.clone(), &data_dir, policy(90, &[("image", 7)])).await.unwrap();
+        assert_eq!(remaining(&conn), [starred]);
+
+        let mut all = policy(90, &[("image", 7)]);
+        all.delete_unstarred_only = false;
+        run_cleanup(conn.clone(), &data_dir, all).await.unwrap();
+        assert!(remaining(&conn).is_empty());
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tracing::info;
+
+use crate::config::Config;
+use crate::error::lock_poisoned;
+use crate::wayland::DataControl;
+
+/// Where the daemon keeps its files, resolved once at startup.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub config: PathBuf,
+    pub data_dir: PathBuf,
+    pub db: PathBuf,
+    pub socket: PathBuf,
+}
+
+impl Paths {
+    /// The defaults, with `--config` and `--data-dir` taking their place when given.
+    pub fn resolve(config: Option<PathBuf>, data_dir: Option<PathBuf>) -> Result<Self> {
+        let config = match config {
+            Some(path) => std::path::absolute(&path).context("failed to resolve config path")?,
+            None => crate::config::default_config_path()?,
+        };
+        let data_dir = match data_dir {
+            Some(dir) => std::path::absolute(&dir).context("failed to resolve data directory")?,
+            None => crate::db::default_data_dir()?,
+        };
+        Ok(Self {
+            config,
+            db: data_dir.join("memoria.db"),
+            data_dir,
+            socket: memoria_client::default_socket_path(),
+        })
+    }
+
+    /// Images as they were copied, named by content hash.
+    pub fn originals_dir(&self) -> PathBuf {
+        self.data_dir.join("images/originals")
+    }
+
+    pub fn thumbs_dir(&self) -> PathBuf {
+        self.data_dir.join("images/thumbs")
+    }
+}
+
+/// State shared between the IPC server and the background tasks.
+pub struct AppState {
+    pub conn: Arc<Mutex<rusqlite::Connection>>,
+    pub cfg: RwLock<Config>,
+    /// Taken by `set_settings` while it writes the config file, which happens
+    /// off the lock on `cfg`.
+    pub settings_write: tokio::sync::Mutex<()>,
+    pub paths: Paths,
+    pub started_at: Instant,
+    /// The watcher's data-control connection when the native backend is in
+    /// use; restores go through it instead of `wl-copy`.
+    pub data_control: OnceLock<Arc<DataControl>>,
+    /// Program (and leading arguments) restores run as `wl-copy`.
+    pub wl_copy_command: Vec<String>,
+    /// Set by the `pause` command; the watcher keeps polling but records
+    /// nothing until `resume`.
+    paused: AtomicBool,
+    /// Timed pause started by `incognito`, separate from `paused`.
+    incognito: Mutex<Option<Incognito>>,
+    /// Hashes the daemon just wrote with `wl-copy`, mapped to when they were
+    /// written, so the watcher doesn't record its own echo. A plain `Mutex`
+    /// suffices: it is only held for a map lookup or insert, never across an
+    /// `.await`, so neither the IPC tasks nor the watcher block on each other.
+    suppressed: Mutex<HashMap<String, Instant>>,
+}
+
+/// An incognito window and the timer that ends it.
+struct Incognito {
+    ends: Instant,
+    /// `ends` as a unix timestamp, for clients.
+    resume_at: i64,
+    timer: tokio::task::AbortHandle,
+}
+
+/// How long a restored hash is ignored by the watcher. Long enough to cover
+/// a poll, short enough that a deliberate re-copy is still recorded.
+const SUPPRESS_WINDOW: Duration = Duration::from_secs(2);
+
+impl AppState {
+    pub fn new(
+        conn: Arc<Mutex<rusqlite::Connection>>,
+        cfg: Config,
+        paths: Paths,
+    ) -> Self {
+        Self {
+            conn,
+            cfg: RwLock::new(cfg),
+            settings_write: tokio::sync::Mutex::new(()),
+            paths,
+            started_at: Instant::now(),
+            data_control: OnceLock::new(),
+            wl_copy_command: vec!["wl-copy".to_string()],
+            paused: AtomicBool::new(false),
+            incognito: Mutex::new(None),
+            suppressed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Marks `hash` as written by the daemon itself.
+    pub fn suppress_hash(&self, hash: String) {
+        if let Ok(mut suppressed) = self.suppressed.lock() {
+            suppressed.insert(hash, Instant::now());
+        }
+    }
+
+    /// Returns whether `hash` was written by the daemon within the suppression
+    /// window, consuming the entry. Expired entries are dropped along the way.
+    pub fn take_suppressed(&self, hash: &str) -> bool {
+        let Ok(mut suppressed) = self.suppressed.lock() else {
+            return false;
+        };
+        suppressed.retain(|_, at| at.elapsed() < SUPPRESS_WINDOW);
+        suppressed.remove(hash).is_some()
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    /// Whether the watcher should record anything: neither paused nor in an
+    /// incognito window.
+    pub fn capturing(&self) -> bool {
+        !self.is_paused() && self.incognito_remaining().is_none()
+    }
+
+    /// Suspends capture for `duration`, replacing any incognito window
+    /// already running. Returns when capture resumes, as a unix timestamp.
+    pub fn start_incognito(self: &Arc<Self>, duration: Duration) -> Result<i64> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).context("system time error")?;
+        let resume_at = (now + duration).as_secs() as i64;
+
+        let mut incognito = self.incognito.lock().map_err(lock_poisoned)?;
+        if let Some(previous) = incognito.take() {
+            previous.timer.abort();
+        }
+        let state = Arc::downgrade(self);
+        let timer = tokio::spawn(async move {
+            tokio::time::sleep(duration).await;
+            if let Some(state) = state.upgrade() {
+                state.expire_incognito();
+            }
+        })
+        .abort_handle();
+        *incognito = Some(Incognito { ends: Instant::now() + duration, resume_at, timer });
+        info!(resume_at, "incognito started, clipboard capture suspended");
+        Ok(resume_at)
+    }
+
+    /// Ends the incognito window early; returns whether one was running.
+    pub fn end_incognito(&self) -> bool {
+        let Ok(mut incognito) = self.incognito.lock() else {
+            return false;
+        };
+        match incognito.take() {
+            Some(window) => {
+                window.timer.abort();
+                info!("incognito ended early");
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Called by the timer. A window that replaced this timer's while it
+    /// waited for the lock has a later end and is left alone.
+    fn expire_incognito(&self) {
+        let Ok(mut incognito) = self.incognito.lock() else {
+            return;
+        };
+        if incognito.as_ref().is_some_and(|window| window.ends <= Instant::now()) {
+            *incognito = None;
+            info!("incognito over, clipboard capture resumed");
+        }
+    }
+
+    /// Time left in the incognito window and when it ends, as a unix
+    /// timestamp; `None` outside one.
+    pub fn incognito_remaining(&self) -> Option<(Duration, i64)> {
+        let incognito = self.incognito.lock().ok()?;
+        let window = incognito.as_ref()?;
+        let remaining = window.ends.checked_duration_since(Instant::now())?;
+        Some((remaining, window.resume_at))
+    }
+
+    pub fn config(&self) -> Result<RwLockReadGuard<'_, Config>> {
+        self.cfg.read().map_err(lock_poisoned)
+    }
+
+    pub fn config_mut(&self) -> Result<RwLockWriteGuard<'_, Config>> {
+        self.cfg.write().map_err(lock_poisoned)
+    }
+}
+
+#[cfg(test)]
+impl AppState {
+    /// Default config over an in-memory database, with files under a fresh
+    /// directory in the system temp dir.
+    pub fn for_tests() -> Arc<Self> {
+        use std::sync::atomic::AtomicUsize;
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+        let data_dir = std::env::temp_dir().join(format!(
+            "memoria-test-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&data_dir).expect("test data directory");
+        let cfg = Config::default();
+        let conn = crate::db::open_and_init(std::path::Path::new(":memory:"), &cfg.storage)
+            .expect("in-memory database");
+        let paths = Paths {
+            config: data_dir.join("config.toml"),
+            db: data_dir.join("memoria.db"),
+            socket: data_dir.join("memoria.sock"),
+            data_dir,
+        };
+        let mut state = Self::new(Arc::new(Mutex::new(conn)), cfg, paths);
+        // Swallows what would be copied, so restores succeed without a
+        // compositor; its arguments land in `$0`, `$1`, ... and are ignored.
+        state.wl_copy_command = ["sh", "-c", "cat > /dev/null", "wl-copy"].map(str::to_string).to_vec();
+        Arc::new(state)
+    }
+}
@@ -0,0 +1,258 @@
+/// Tags that start a new line when stripped.
+const BREAK_TAGS: &[&str] = &[
+    "br", "p", "div", "li", "tr", "h1", "h2", "h3", "h4", "h5", "h6", "pre", "blockquote",
+];
+
+/// Elements whose contents are never visible text.
+const HIDDEN_TAGS: &[&str] = &["script", "style", "head", "title"];
+
+/// Cheap check for whether `text` contains markup worth stripping: at least
+/// one `<tag ...>` or `</tag>` whose name starts with an ASCII letter.
+pub fn looks_like_html(text: &str) -> bool {
+    let mut rest = text;
+    while let Some(start) = rest.find('<') {
+        rest = &rest[start + 1..];
+        let name = rest.strip_prefix('/').unwrap_or(rest);
+        if name.starts_with(|c: char| c.is_ascii_alphabetic()) && rest.contains('>') {
+            return true;
+        }
+    }
+    false
+}
+
+/// Converts an HTML fragment to plain text: tags are dropped, block-level
+/// tags and `<br>` become newlines, and common entities are decoded.
+pub fn html_to_text(html: &str) -> String {
+    let mut out = String::with_capacity(html.len());
+    let mut hidden_until: Option<String> = None;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        let Some(start) = rest.find('<') else {
+            if hidden_until.is_none() {
+                out.push_str(&decode_entities(rest));
+            }
+            break;
+        };
+        if hidden_until.is_none() {
+            out.push_str(&decode_entities(&rest[..start]));
+        }
+
+        let Some(end) = rest[start..].find('>') else {
+            // Unterminated tag; keep the remainder as text.
+            if hidden_until.is_none() {
+                out.push_str(&decode_entities(&rest[start..]));
+            }
+            break;
+        };
+        let tag = &rest[start + 1..start + end];
+        rest = &rest[start + end + 1..];
+
+        let closing = tag.starts_with('/');
+        let name: String = tag
+            .trim_start_matches('/')
+            .chars()
+            .take_while(|c| c.is_ascii_alphanumeric())
+            .collect::<String>()
+            .to_ascii_lowercase();
+
+        if let Some(hidden) = &hidden_until {
+            if closing && *hidden == name {
+                hidden_until = None;
+            }
+            continue;
+        }
+        if !closing && HIDDEN_TAGS.contains(&name.as_str()) && !tag.ends_with('/') {
+            hidden_until = Some(name);
+            continue;
+        }
+        if BREAK_TAGS.contains(&name.as_str()) && !out.is_empty() && !out.ends_with('\n') {
+            out.push('\n');
+        }
+    }
+
+    out.trim().to_string()
+}
+
+/// Whether `text` matches the glob `pattern`, where `*` is any run of
+/// characters and `?` any one. Case is ignored.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    fn matches(pattern: &[char], text: &[char]) -> bool {
+        match pattern.split_first() {
+            None => text.is_empty(),
+            Some(('*', rest)) => (0..=text.len()).any(|skip| matches(rest, &text[skip..])),
+            Some((&p, rest)) => match text.split_first() {
+                Some((&t, text)) => (p == '?' || p == t) && matches(rest, text),
+                None => false,
+            },
+        }
+    }
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    matches(&pattern, &text)
+}
+
+/// Cuts `text` to at most `max_chars` characters, on a char boundary.
+/// Returns the prefix and whether anything was cut.
+pub fn truncate_chars(text: &str, max_chars: usize) -> (&str, bool) {
+    match text.char_indices().nth(max_chars) {
+        Some((end, _)) => (&text[..end], true),
+        None => (text, false),
+    }
+}
+
+fn decode_entities(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(start) = rest.find('&') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+
+        let decoded = rest[1..]
+            .find(';')
+            .filter(|&end| end > 0 && end <= 10)
+            .and_then(|end| decode_entity(&rest[1..=end]).map(|c| (c, end + 2)));
+
+        match decoded {
+            Some((c, len)) => {
+                out.push(c);
+                rest = &rest[len..];
+            }
+            None => {
+                out.push('&');
+                rest = &rest[1..];
+            }
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+fn decode_entity(name: &str) -> Option<char> {
+    if let Some(num) = name.strip_prefix('#') {
+        let code = match num.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => num.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => ' ',
+        "copy" => '©',
+        "reg" => '®',
+        "hellip" => '…',
+        "mdash" => '—',
+        "ndash" => '–',
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_entities_are_decoded() {
+        assert_eq!(html_to_text("Tom &amp; Jerry &lt;3 &quot;cheese&quot;"), "Tom & Jerry <3 \"cheese\"");
+        assert_eq!(html_to_text("&#65;&#x42;&#X43; &hellip; &copy;"), "ABC … ©");
+        // Unknown or unterminated entities are kept as written.
+        assert_eq!(html_to_text("AT&T &bogus; &amp"), "AT&T &bogus; &amp");
+    }
+
+    #[test]
+    fn line_breaks_become_newlines() {
+        assert_eq!(html_to_text("one<br>two<br/>three<BR />four"), "one\ntwo\nthree\nfour");
+        assert_eq!(html_to_text("<p>first</p><p>second</p>"), "first\nsecond");
+        assert_eq!(html_to_text("<ul><li>a</li><li>b</li></ul>"), "a\nb");
+    }
+
+    #[test]
+    fn nested_tags_are_stripped() {
+        assert_eq!(
+            html_to_text(r#"<div class="x"><span>Hello, <b><i>nested</i></b> world</span></div>"#),
+            "Hello, nested world"
+        );
+        assert_eq!(html_to_text("<a href=\"https://x.test\">link</a>"), "link");
+    }
+
+    #[test]
+    fn hidden_elements_are_dropped() {
+        assert_eq!(
+            html_to_text("<head><title>T</title><style>p { color: red }</style></head><p>body</p><script>x()</script>"),
+            "body"
+        );
+    }
+
+    #[test]
+    fn unterminated_tags_stay_text() {
+        assert_eq!(html_to_text("a < b"), "a < b");
+    }
+
+    #[test]
+    fn truncates_on_char_boundaries() {
+        assert_eq!(truncate_chars("hello", 10), ("hello", false));
+        assert_eq!(truncate_chars("hello", 5), ("hello", false));
+        assert_eq!(truncate_chars("hello", 4), ("hell", true));
+        assert_eq!(truncate_chars("hello", 0), ("", true));
+        assert_eq!(truncate_chars("", 0), ("", false));
+    }
+
+    #[test]
+    fn truncates_multibyte_text_without_splitting_chars() {
+        assert_eq!(truncate_chars("😀😃😄😁", 2), ("😀😃", true));
+        assert_eq!(truncate_chars("東京都渋谷区", 3), ("東京都", true));
+        // A family emoji is several chars joined; only whole chars are kept.
+        assert_eq!(truncate_chars("👨‍👩‍👧", 1), ("👨", true));
+        assert_eq!(truncate_chars("añb", 2), ("añ", true));
+    }
+
+    #[test]
+    fn detects_markup() {
+        assert!(looks_like_html("<b>bold</b>"));
+        assert!(looks_like_html("text</p>"));
+        assert!(!looks_like_html("a < b and c > d"));
+        assert!(!looks_like_html("1 <2"));
+        assert!(!looks_like_html("plain text"));
+    }
+
+    #[test]
+    fn glob_matches_exactly_without_wildcards() {
+        assert!(glob_match("firefox", "firefox"));
+        assert!(glob_match("", ""));
+        assert!(!glob_match("firefox", "firefox-esr"));
+        assert!(!glob_match("firefox", "fire"));
+    }
+
+    #[test]
+    fn glob_star_matches_any_run() {
+        assert!(glob_match("org.kde.*", "org.kde.dolphin"));
+        assert!(glob_match("org.kde.*", "org.kde."));
+        assert!(glob_match("*pass*", "KeePassXC"));
+        assert!(glob_match("image/*", "image/png"));
+        assert!(glob_match("*", ""));
+        assert!(!glob_match("org.kde.*", "org.gnome.Nautilus"));
+        assert!(!glob_match("image/*", "text/plain"));
+    }
+
+    #[test]
+    fn glob_question_mark_matches_one_char() {
+        assert!(glob_match("kitty-?", "kitty-1"));
+        assert!(glob_match("??", "日本"));
+        assert!(!glob_match("kitty-?", "kitty-"));
+        assert!(!glob_match("kitty-?", "kitty-12"));
+    }
+
+    #[test]
+    fn glob_ignores_case() {
+        assert!(glob_match("KeePassXC", "keepassxc"));
+        assert!(glob_match("org.KDE.*", "ORG.kde.Dolphin"));
+    }
+}
@@ -0,0 +1,337 @@
+use anyhow::{anyhow, Context, Result};
+use std::io::Write;
+use std::os::fd::{AsFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncReadExt;
+use tokio::sync::Notify;
+use tracing::{debug, error, info, warn};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::{wl_registry::WlRegistry, wl_seat::WlSeat};
+use wayland_client::{delegate_noop, event_created_child, Connection, Dispatch, Proxy, QueueHandle};
+use wayland_protocols_wlr::data_control::v1::client::{
+    zwlr_data_control_device_v1::{self, ZwlrDataControlDeviceV1},
+    zwlr_data_control_manager_v1::ZwlrDataControlManagerV1,
+    zwlr_data_control_offer_v1::{self, ZwlrDataControlOfferV1},
+    zwlr_data_control_source_v1::{self, ZwlrDataControlSourceV1},
+};
+
+use crate::backend::ClipboardBackend;
+use crate::clipboard::Selection;
+
+/// Text mimes tried, in order, when `text/plain` itself isn't offered.
+const TEXT_MIMES: &[&str] = &["text/plain;charset=utf-8", "UTF8_STRING", "STRING", "TEXT"];
+
+/// Longest wait for the source client to finish writing an offer.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Reads selections through the compositor's `zwlr_data_control_manager_v1`,
+/// without spawning processes. Offers arrive on a dedicated thread; reads
+/// receive from the very offer whose mimes were seen, so a change between
+/// listing and reading can't mix contents. Selections are set the same
+/// way: `publish` offers every representation at once.
+pub struct DataControl {
+    conn: Connection,
+    qh: QueueHandle<Handler>,
+    manager: ZwlrDataControlManagerV1,
+    device: ZwlrDataControlDeviceV1,
+    shared: Arc<Shared>,
+}
+
+#[derive(Default)]
+struct Shared {
+    /// Current offer per selection, indexed by `slot`.
+    offers: Mutex<[Option<ZwlrDataControlOfferV1>; 2]>,
+    changed: [Notify; 2],
+    closed: AtomicBool,
+}
+
+/// Mimes announced for an offer, filled in before it becomes a selection.
+#[derive(Default)]
+struct OfferMimes(Mutex<Vec<String>>);
+
+/// Representations served by a source the daemon offered.
+struct SourceData(Vec<(String, Arc<[u8]>)>);
+
+fn slot(selection: Selection) -> usize {
+    match selection {
+        Selection::Clipboard => 0,
+        Selection::Primary => 1,
+    }
+}
+
+impl DataControl {
+    /// Connects to `$WAYLAND_DISPLAY` and starts listening for selections.
+    /// Fails if the compositor lacks the data-control protocol.
+    pub fn connect() -> Result<Self> {
+        let conn = Connection::connect_to_env().context("failed to connect to the Wayland display")?;
+        let (globals, mut queue) =
+            registry_queue_init::<Handler>(&conn).context("failed to list Wayland globals")?;
+        let qh = queue.handle();
+
+        let seat: WlSeat = globals.bind(&qh, 1..=1, ()).context("compositor has no seat")?;
+        let manager: ZwlrDataControlManagerV1 = globals
+            .bind(&qh, 1..=2, ())
+            .context("compositor does not support zwlr_data_control_manager_v1")?;
+        if manager.version() < 2 {
+            warn!("compositor's data-control protocol predates primary selection support");
+        }
+        let device = manager.get_data_device(&seat, &qh, ());
+
+        let shared = Arc::new(Shared::default());
+        let mut handler = Handler { shared: shared.clone() };
+        std::thread::Builder::new()
+            .name("wayland-clipboard".to_string())
+            .spawn(move || {
+                loop {
+                    if let Err(err) = queue.blocking_dispatch(&mut handler) {
+                        error!(error=%err, "lost the Wayland connection, clipboard monitoring stopped");
+                        handler.shared.closed.store(true, Ordering::Relaxed);
+                        break;
+                    }
+                    if handler.shared.closed.load(Ordering::Relaxed) {
+                        break;
+                    }
+                }
+            })
+            .context("failed to start the Wayland event thread")?;
+
+        info!(version = manager.version(), "connected to wlr data-control");
+        Ok(Self { conn, qh, manager, device, shared })
+    }
+
+    /// Makes the daemon the owner of `selection`, offering each of `reps`
+    /// as `(mime, bytes)`. `text/plain` is also offered under the aliases
+    /// clients commonly ask for. Served until another client takes over.
+    pub fn publish(&self, selection: Selection, reps: Vec<(String, Vec<u8>)>) -> Result<()> {
+        if self.shared.closed.load(Ordering::Relaxed) {
+            return Err(anyhow!("Wayland connection closed"));
+        }
+        if selection == Selection::Primary && self.manager.version() < 2 {
+            return Err(anyhow!("compositor can't set the primary selection over data-control"));
+        }
+
+        let mimes: Vec<String> = reps
+            .iter()
+            .flat_map(|(mime, _)| {
+                let aliases: &[&str] = if mime == "text/plain" { TEXT_MIMES } else { &[] };
+                std::iter::once(mime.clone()).chain(aliases.iter().map(|alias| alias.to_string()))
+            })
+            .collect();
+        let data = SourceData(reps.into_iter().map(|(mime, bytes)| (mime, Arc::from(bytes))).collect());
+
+        let source = self.manager.create_data_source(&self.qh, data);
+        for mime in mimes {
+            source.offer(mime);
+        }
+        match selection {
+            Selection::Clipboard => self.device.set_selection(Some(&source)),
+            Selection::Primary => self.device.set_primary_selection(Some(&source)),
+        }
+        self.conn.flush().context("failed to flush Wayland requests")?;
+        Ok(())
+    }
+
+    fn offer(&self, selection: Selection) -> Option<ZwlrDataControlOfferV1> {
+        self.shared.offers.lock().ok()?[slot(selection)].clone()
+    }
+}
+
+impl ClipboardBackend for DataControl {
+    fn name(&self) -> &'static str {
+        "data-control"
+    }
+
+    async fn changed(&self, selection: Selection) {
+        if self.shared.closed.load(Ordering::Relaxed) {
+            // Nothing will change again; don't spin the watcher.
+            std::future::pending::<()>().await;
+        }
+        self.shared.changed[slot(selection)].notified().await;
+    }
+
+    async fn mimes(&self, selection: Selection) -> Result<Vec<String>> {
+        if self.shared.closed.load(Ordering::Relaxed) {
+            return Err(anyhow!("Wayland connection closed"));
+        }
+        Ok(self
+            .offer(selection)
+            .and_then(|offer| offer.data::<OfferMimes>().and_then(|mimes| mimes.0.lock().ok().map(|m| m.clone())))
+            .unwrap_or_default())
+    }
+
+    async fn read(&self, selection: Selection, mime: &str) -> Result<Vec<u8>> {
+        if self.shared.closed.load(Ordering::Relaxed) {
+            return Err(anyhow!("Wayland connection closed"));
+        }
+        let Some(offer) = self.offer(selection) else {
+            return Ok(Vec::new());
+        };
+        let Some(offered) = offer
+            .data::<OfferMimes>()
+            .and_then(|mimes| mimes.0.lock().ok())
+            .and_then(|mimes| pick_mime(&mimes, mime))
+        else {
+            return Ok(Vec::new());
+        };
+
+        let (reader, writer) = pipe().context("failed to create pipe")?;
+        offer.receive(offered.clone(), writer.as_fd());
+        self.conn.flush().context("failed to flush Wayland requests")?;
+        // The source client holds its own copy; ours must close for EOF.
+        drop(writer);
+
+        let mut reader = tokio::net::unix::pipe::Receiver::from_owned_fd(reader)
+            .context("failed to register pipe")?;
+        let mut data = Vec::new();
+        tokio::time::timeout(READ_TIMEOUT, reader.read_to_end(&mut data))
+            .await
+            .map_err(|_| anyhow!("timed out reading {offered} from the selection owner"))?
+            .with_context(|| format!("failed to read {offered} from the selection owner"))?;
+
+        debug!(selection = selection.as_str(), mime=%offered, bytes = data.len(), "read offer");
+        Ok(data)
+    }
+}
+
+/// The offered mime to receive for a requested `mime`. `text/plain` also
+/// accepts the usual charset-qualified and X11-style spellings.
+fn pick_mime(offered: &[String], mime: &str) -> Option<String> {
+    if offered.iter().any(|m| m == mime) {
+        return Some(mime.to_string());
+    }
+    if mime != "text/plain" {
+        return None;
+    }
+    TEXT_MIMES
+        .iter()
+        .find(|candidate| offered.iter().any(|m| m == *candidate))
+        .map(|m| m.to_string())
+}
+
+/// A close-on-exec pipe as `(read, write)`.
+fn pipe() -> std::io::Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+struct Handler {
+    shared: Arc<Shared>,
+}
+
+impl Handler {
+    /// Makes `offer` the current one for `selection`, destroying the offer it
+    /// replaces, and wakes the watcher.
+    fn set_selection(&mut self, selection: Selection, offer: Option<ZwlrDataControlOfferV1>) {
+        let Ok(mut offers) = self.shared.offers.lock() else {
+            return;
+        };
+        if let Some(old) = offers[slot(selection)].take() {
+            if offers[1 - slot(selection)].as_ref() != Some(&old) {
+                old.destroy();
+            }
+        }
+        offers[slot(selection)] = offer;
+        drop(offers);
+        self.shared.changed[slot(selection)].notify_one();
+    }
+}
+
+impl Dispatch<WlRegistry, GlobalListContents> for Handler {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ZwlrDataControlSourceV1, SourceData> for Handler {
+    fn event(
+        _: &mut Self,
+        source: &ZwlrDataControlSourceV1,
+        event: zwlr_data_control_source_v1::Event,
+        data: &SourceData,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_source_v1::Event::Send { mime_type, fd } => {
+                let offered: Vec<String> = data.0.iter().map(|(mime, _)| mime.clone()).collect();
+                let served = match offered.iter().position(|mime| *mime == mime_type) {
+                    Some(idx) => Some(idx),
+                    None if TEXT_MIMES.contains(&mime_type.as_str()) => {
+                        offered.iter().position(|mime| mime == "text/plain")
+                    }
+                    None => None,
+                };
+                let Some(idx) = served else {
+                    return;
+                };
+                // A slow reader must not stall the event thread.
+                let bytes = data.0[idx].1.clone();
+                std::thread::spawn(move || {
+                    if let Err(err) = std::fs::File::from(fd).write_all(&bytes) {
+                        debug!(mime=%mime_type, error=%err, "failed to serve offered selection");
+                    }
+                });
+            }
+            zwlr_data_control_source_v1::Event::Cancelled => source.destroy(),
+            _ => {}
+        }
+    }
+}
+
+delegate_noop!(Handler: ignore WlSeat);
+delegate_noop!(Handler: ZwlrDataControlManagerV1);
+
+impl Dispatch<ZwlrDataControlDeviceV1, ()> for Handler {
+    fn event(
+        state: &mut Self,
+        _: &ZwlrDataControlDeviceV1,
+        event: zwlr_data_control_device_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zwlr_data_control_device_v1::Event::Selection { id } => state.set_selection(Selection::Clipboard, id),
+            zwlr_data_control_device_v1::Event::PrimarySelection { id } => {
+                state.set_selection(Selection::Primary, id)
+            }
+            zwlr_data_control_device_v1::Event::Finished => {
+                error!("compositor withdrew the data-control device, clipboard monitoring stopped");
+                state.shared.closed.store(true, Ordering::Relaxed);
+            }
+            _ => {}
+        }
+    }
+
+    event_created_child!(Handler, ZwlrDataControlDeviceV1, [
+        zwlr_data_control_device_v1::EVT_DATA_OFFER_OPCODE => (ZwlrDataControlOfferV1, OfferMimes::default()),
+    ]);
+}
+
+impl Dispatch<ZwlrDataControlOfferV1, OfferMimes> for Handler {
+    fn event(
+        _: &mut Self,
+        _: &ZwlrDataControlOfferV1,
+        event: zwlr_data_control_offer_v1::Event,
+        mimes: &OfferMimes,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let zwlr_data_control_offer_v1::Event::Offer { mime_type } = event {
+            if let Ok(mut mimes) = mimes.0.lock() {
+                mimes.push(mime_type);
+            }
+        }
+    }
+}